@@ -1,10 +1,12 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use quote::{quote, quote_spanned};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use std::convert::TryFrom;
 use syn::{
-    parse_macro_input, parse_quote, spanned::Spanned, Attribute, Data, DeriveInput, Fields,
-    GenericParam, Generics, Lit, LitInt, Meta,
+    parse_macro_input, parse_quote, spanned::Spanned, Data, DataEnum, DeriveInput, Expr, Fields,
+    GenericParam, Lit, LitStr, Meta, NestedMeta,
 };
 
 #[proc_macro_derive(Constructor)]
@@ -15,20 +17,22 @@ pub fn constructor(input: TokenStream) -> TokenStream {
 }
 
 fn impl_construct_packet_macro(ast: syn::DeriveInput) -> TokenStream {
+    let (parameters, value) = match params_and_value(&ast) {
+        Ok(params_and_value) => params_and_value,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
     let name = ast.ident;
 
     let generics = add_trait_bounds(ast.generics);
 
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let (parameters, values) = params_and_values(&ast.data);
-
     let expanded = quote! {
         impl #impl_generics #name #ty_generics #where_clause {
+            #[allow(clippy::too_many_arguments)]
             pub fn new(#parameters) -> Self {
-                Self {
-                    #values
-                }
+                #value
             }
         }
     };
@@ -36,45 +40,32 @@ fn impl_construct_packet_macro(ast: syn::DeriveInput) -> TokenStream {
     expanded.into()
 }
 
-fn params_and_values(data: &Data) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
-    match *data {
+fn params_and_value(ast: &DeriveInput) -> Result<(TokenStream2, TokenStream2), syn::Error> {
+    match ast.data {
         Data::Struct(ref data) => {
-            match data.fields {
-                Fields::Named(ref fields) => {
-                    let mut parameters = Vec::new();
-                    let mut values = Vec::new();
-
-                    for f in fields.named.iter() {
-                        let ident = &f.ident;
-                        let ty = &f.ty;
-                        // This is a hack because there is no easy way to support both
-                        // SizedDataTypes and DataTypes.
-                        parameters.push(quote_spanned! {f.span()=>
-                            #ident: #ty
-                        });
-                        values.push(quote_spanned! {f.span()=>
-                            #ident
-                        });
-                    }
+            let mut parameters = Vec::new();
 
-                    let parameters = quote! {
-                        #(#parameters),*
-                    };
-                    let values = quote! {
-                        #(#values),*
-                    };
-
-                    (parameters.into(), values.into())
-                }
-                Fields::Unit => unimplemented!(),
-                Fields::Unnamed(_) => unimplemented!(),
+            for (f, binding) in data.fields.iter().zip(bindings(&data.fields)) {
+                let ty = &f.ty;
+                parameters.push(quote_spanned! {f.span()=>
+                    #binding: #ty
+                });
             }
+
+            let parameters = quote! {
+                #(#parameters),*
+            };
+
+            Ok((parameters, pattern(quote!(Self), &data.fields)))
         }
-        Data::Enum(_) | Data::Union(_) => unimplemented!(),
+        Data::Enum(_) | Data::Union(_) => Err(syn::Error::new_spanned(
+            ast,
+            "Constructor can only be derived for structs",
+        )),
     }
 }
 
-#[proc_macro_derive(IntoPacket, attributes(packet_id))]
+#[proc_macro_derive(IntoPacket, attributes(packet_id, discriminant, value))]
 pub fn derive_into_packet(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
@@ -82,22 +73,41 @@ pub fn derive_into_packet(input: TokenStream) -> TokenStream {
 }
 
 fn impl_derive_into_packet(ast: syn::DeriveInput) -> TokenStream {
-    let packet_id = match get_packet_id(ast.span(), ast.attrs) {
+    let packet_id = match get_packet_id(ast.span(), &ast.attrs) {
         Ok(packet_id) => packet_id,
         Err(e) => return e.to_compile_error().into(),
     };
 
+    let (sum, writes) = match ast.data {
+        Data::Struct(ref data) => sum_and_writes(&data.fields),
+        Data::Enum(ref data) => {
+            let discriminant = match Discriminant::from_attrs(&ast.attrs) {
+                Ok(discriminant) => discriminant,
+                Err(e) => return e.to_compile_error().into(),
+            };
+
+            match enum_size_and_write(data, discriminant, &quote!(&mut data)) {
+                Ok((size, write)) => (quote!({ let this = &self; #size }), write),
+                Err(e) => return e.to_compile_error().into(),
+            }
+        }
+        Data::Union(_) => {
+            return syn::Error::new_spanned(&ast, "IntoPacket can't be derived for unions")
+                .to_compile_error()
+                .into()
+        }
+    };
+
     let name = ast.ident;
 
     let generics = add_trait_bounds(ast.generics);
 
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let (sum, writes) = sum_and_writes(&ast.data);
-
     let expanded = quote! {
         impl #impl_generics crate::protocol::packets::IntoPacket for #name #ty_generics #where_clause {
             fn into_packet(self) -> crate::protocol::packets::ClientboundPacket {
+                #[allow(unused_mut)]
                 let mut data = bytes::BytesMut::with_capacity(#sum);
 
                 #writes
@@ -112,24 +122,433 @@ fn impl_derive_into_packet(ast: syn::DeriveInput) -> TokenStream {
 
 fn get_packet_id(
     global_span: proc_macro2::Span,
-    attrs: Vec<syn::Attribute>,
+    attrs: &[syn::Attribute],
 ) -> Result<syn::LitInt, syn::Error> {
+    get_int_attr("packet_id", attrs)?
+        .ok_or_else(|| syn::Error::new(global_span, "Packet ID must be supplied"))
+}
+
+/// Derives `FromPacket`, reading each field in order and failing if any bytes
+/// are left over. A `#[packet_id = N]` also derives `PacketId`, which lets the
+/// packet be listed in `serverbound_packets!`.
+#[proc_macro_derive(FromPacket, attributes(packet_id, max_len, discriminant, value))]
+pub fn derive_from_packet(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    impl_derive_from_packet(input)
+}
+
+fn impl_derive_from_packet(ast: syn::DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+
+    let reads = match ast.data {
+        Data::Struct(ref data) => read_fields(
+            quote!(Self),
+            &data.fields,
+            &quote!(&mut data),
+            &name.to_string(),
+        ),
+        Data::Enum(ref data) => Discriminant::from_attrs(&ast.attrs)
+            .and_then(|discriminant| enum_read(name, data, discriminant, &quote!(&mut data))),
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            &ast,
+            "FromPacket can't be derived for unions",
+        )),
+    };
+
+    let reads = match reads {
+        Ok(reads) => reads,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let packet_id = match get_int_attr("packet_id", &ast.attrs) {
+        Ok(packet_id) => packet_id,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let generics = add_trait_bounds(ast.generics);
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let packet_id_impl = packet_id.map(|packet_id| {
+        quote! {
+            impl #impl_generics crate::protocol::packets::PacketId for #name #ty_generics #where_clause {
+                const PACKET_ID: i32 = #packet_id;
+            }
+        }
+    });
+
+    let expanded = quote! {
+        #packet_id_impl
+
+        impl #impl_generics crate::protocol::packets::FromPacket for #name #ty_generics #where_clause {
+            fn from_packet(
+                packet: crate::protocol::packets::ServerboundPacket,
+            ) -> anyhow::Result<Self> {
+                #[allow(unused_mut)]
+                let mut data = packet.data();
+
+                let packet = #reads;
+
+                if !data.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "{} bytes left over after reading {} packet",
+                        data.len(),
+                        stringify!(#name)
+                    ));
+                }
+
+                Ok(packet)
+            }
+        }
+    };
+
+    expanded.into()
+}
+
+/// Derives `DataType` for an enum, written as its discriminant followed by the
+/// variant's fields. The discriminant's type is set with
+/// `#[discriminant(VarInt)]` (the default), `#[discriminant(Byte)]` or
+/// `#[discriminant(UnsignedByte)]`, and each variant's value is taken from
+/// `#[value = N]`, its Rust discriminant, or one more than the last variant.
+#[proc_macro_derive(DataType, attributes(max_len, discriminant, value))]
+pub fn derive_data_type(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    impl_derive_data_type(input)
+}
+
+fn impl_derive_data_type(ast: syn::DeriveInput) -> TokenStream {
+    let name = ast.ident;
+
+    let data = match ast.data {
+        Data::Enum(ref data) => data,
+        Data::Struct(_) | Data::Union(_) => {
+            return syn::Error::new(name.span(), "DataType can only be derived for enums")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let impls = Discriminant::from_attrs(&ast.attrs).and_then(|discriminant| {
+        let read = enum_read(&name, data, discriminant, &quote!(src))?;
+        let (size, write) = enum_size_and_write(data, discriminant, &quote!(dst))?;
+        Ok((read, size, write))
+    });
+
+    let (read, size, write) = match impls {
+        Ok(impls) => impls,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let generics = add_trait_bounds(ast.generics);
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let expanded = quote! {
+        impl #impl_generics crate::protocol::data_types::DataType for #name #ty_generics #where_clause {
+            fn read_from(
+                src: &mut bytes::BytesMut,
+            ) -> crate::protocol::data_types::Result<Self> {
+                Ok(#read)
+            }
+
+            fn write_to(self, dst: &mut bytes::BytesMut) {
+                #write
+            }
+
+            fn size(&self) -> usize {
+                let this = self;
+                #size
+            }
+        }
+    };
+
+    expanded.into()
+}
+
+/// The data type an enum's discriminant is written as.
+#[derive(Copy, Clone)]
+enum Discriminant {
+    VarInt,
+    Byte,
+    UnsignedByte,
+}
+
+impl Discriminant {
+    fn from_attrs(attrs: &[syn::Attribute]) -> Result<Discriminant, syn::Error> {
+        for attr in attrs {
+            if let Ok(Meta::List(list)) = attr.parse_meta() {
+                if !list.path.is_ident("discriminant") {
+                    continue;
+                }
+
+                if let Some(NestedMeta::Meta(Meta::Path(path))) = list.nested.first() {
+                    if path.is_ident("VarInt") {
+                        return Ok(Discriminant::VarInt);
+                    } else if path.is_ident("Byte") {
+                        return Ok(Discriminant::Byte);
+                    } else if path.is_ident("UnsignedByte") {
+                        return Ok(Discriminant::UnsignedByte);
+                    }
+                }
+
+                return Err(syn::Error::new(
+                    list.span(),
+                    "Discriminants must be one of VarInt, Byte or UnsignedByte",
+                ));
+            }
+        }
+
+        Ok(Discriminant::VarInt)
+    }
+
+    /// An expression reading the discriminant as an i32.
+    fn read(self, src: &TokenStream2) -> TokenStream2 {
+        match self {
+            Discriminant::VarInt => quote! {
+                <crate::protocol::data_types::VarInt as crate::protocol::data_types::DataType>::read_from(#src)?.value()
+            },
+            Discriminant::Byte => quote! {
+                <crate::protocol::data_types::Byte as crate::protocol::data_types::DataType>::read_from(#src)? as i32
+            },
+            Discriminant::UnsignedByte => quote! {
+                <crate::protocol::data_types::UnsignedByte as crate::protocol::data_types::DataType>::read_from(#src)? as i32
+            },
+        }
+    }
+
+    /// Whether a variant's value can be written as this data type.
+    fn fits(self, value: i32) -> bool {
+        match self {
+            Discriminant::VarInt => true,
+            Discriminant::Byte => i8::try_from(value).is_ok(),
+            Discriminant::UnsignedByte => u8::try_from(value).is_ok(),
+        }
+    }
+
+    /// An expression for the discriminant's value as its data type.
+    fn value(self, value: i32) -> TokenStream2 {
+        match self {
+            Discriminant::VarInt => quote!(crate::protocol::data_types::VarInt::new(#value)),
+            Discriminant::Byte => {
+                let value = value as i8;
+                quote!(#value)
+            }
+            Discriminant::UnsignedByte => {
+                let value = value as u8;
+                quote!(#value)
+            }
+        }
+    }
+}
+
+/// Works out the value written for each variant of an enum, checking each one
+/// fits in the discriminant's data type.
+fn variant_values(data: &DataEnum, discriminant: Discriminant) -> Result<Vec<i32>, syn::Error> {
+    let mut values = Vec::new();
+    let mut next = 0;
+
+    for variant in data.variants.iter() {
+        let value = match get_int_attr("value", &variant.attrs)? {
+            Some(value) => value.base10_parse()?,
+            None => match variant.discriminant {
+                Some((_, Expr::Lit(ref lit))) => match lit.lit {
+                    Lit::Int(ref value) => value.base10_parse()?,
+                    _ => {
+                        return Err(syn::Error::new(
+                            lit.span(),
+                            "Discriminants must be integers",
+                        ))
+                    }
+                },
+                Some((_, ref expr)) => {
+                    return Err(syn::Error::new(
+                        expr.span(),
+                        "Discriminants must be integer literals",
+                    ))
+                }
+                None => next,
+            },
+        };
+
+        if !discriminant.fits(value) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                format!("{} doesn't fit in the enum's discriminant", value),
+            ));
+        }
+
+        values.push(value);
+        next = value + 1;
+    }
+
+    Ok(values)
+}
+
+/// Builds an expression reading an enum's discriminant and then the fields of
+/// the matching variant.
+fn enum_read(
+    name: &syn::Ident,
+    data: &DataEnum,
+    discriminant: Discriminant,
+    src: &TokenStream2,
+) -> Result<TokenStream2, syn::Error> {
+    let values = variant_values(data, discriminant)?;
+
+    let mut arms = Vec::new();
+    for (variant, value) in data.variants.iter().zip(values) {
+        let ident = &variant.ident;
+        let context = format!("{}::{}", name, ident);
+        let read = read_fields(quote!(Self::#ident), &variant.fields, src, &context)?;
+
+        arms.push(quote_spanned! {variant.span()=>
+            #value => #read
+        });
+    }
+
+    let read_discriminant = discriminant.read(src);
+
+    Ok(quote! {
+        match #read_discriminant {
+            #(#arms,)*
+            other => {
+                return Err(crate::protocol::data_types::DataTypeError::Malformed(
+                    stringify!(#name).to_string(),
+                    format!("unknown discriminant {}", other),
+                )
+                .into())
+            }
+        }
+    })
+}
+
+/// Builds an expression for the size of the enum bound to `this`, and the
+/// statements writing `self` to `dst`.
+fn enum_size_and_write(
+    data: &DataEnum,
+    discriminant: Discriminant,
+    dst: &TokenStream2,
+) -> Result<(TokenStream2, TokenStream2), syn::Error> {
+    let values = variant_values(data, discriminant)?;
+
+    let mut size_arms = Vec::new();
+    let mut write_arms = Vec::new();
+    for (variant, value) in data.variants.iter().zip(values) {
+        let ident = &variant.ident;
+        let pattern = pattern(quote!(Self::#ident), &variant.fields);
+        let bindings = bindings(&variant.fields);
+        let value = discriminant.value(value);
+
+        size_arms.push(quote_spanned! {variant.span()=>
+            #pattern => {
+                crate::protocol::data_types::DataType::size(&#value) #(+ #bindings.size())*
+            }
+        });
+        write_arms.push(quote_spanned! {variant.span()=>
+            #pattern => {
+                crate::protocol::data_types::DataType::write_to(#value, #dst);
+                #(#bindings.write_to(#dst);)*
+            }
+        });
+    }
+
+    let size = quote! {
+        match this {
+            #(#size_arms)*
+        }
+    };
+    let write = quote! {
+        match self {
+            #(#write_arms)*
+        }
+    };
+
+    Ok((size, write))
+}
+
+/// The names fields are bound to when constructing or destructuring them.
+fn bindings(fields: &Fields) -> Vec<syn::Ident> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match f.ident {
+            Some(ref ident) => ident.clone(),
+            None => format_ident!("field_{}", i),
+        })
+        .collect()
+}
+
+/// Builds `path { a, b }`, `path(field_0, field_1)` or `path`, which both
+/// constructs from and destructures into the field bindings.
+fn pattern(path: TokenStream2, fields: &Fields) -> TokenStream2 {
+    let bindings = bindings(fields);
+
+    match fields {
+        Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => path,
+    }
+}
+
+/// Builds an expression constructing `path` by reading each field in order,
+/// using the field's `SizedDataType` implementation if it has a `#[max_len]`.
+fn read_fields(
+    path: TokenStream2,
+    fields: &Fields,
+    src: &TokenStream2,
+    context: &str,
+) -> Result<TokenStream2, syn::Error> {
+    let mut reads = Vec::new();
+
+    for (f, binding) in fields.iter().zip(bindings(fields)) {
+        let ty = &f.ty;
+        let context = LitStr::new(&format!("While reading {}::{}", context, binding), f.span());
+
+        let read = match get_int_attr("max_len", &f.attrs)? {
+            Some(max_len) => quote_spanned! {f.span()=>
+                <#ty as crate::protocol::data_types::SizedDataType>::read_from_sized(
+                    #src,
+                    #max_len,
+                )
+            },
+            None => quote_spanned! {f.span()=>
+                <#ty as crate::protocol::data_types::DataType>::read_from(#src)
+            },
+        };
+
+        reads.push(quote_spanned! {f.span()=>
+            let #binding = #read.map_err(|e| e.add_context(#context))?;
+        });
+    }
+
+    let value = pattern(path, fields);
+
+    Ok(quote! {
+        {
+            #(#reads)*
+            #value
+        }
+    })
+}
+
+fn get_int_attr(name: &str, attrs: &[syn::Attribute]) -> Result<Option<syn::LitInt>, syn::Error> {
     for attr in attrs {
         if let Ok(Meta::NameValue(name_value)) = attr.parse_meta() {
-            if name_value.path.is_ident("packet_id") {
+            if name_value.path.is_ident(name) {
                 if let Lit::Int(v) = name_value.lit {
-                    return Ok(v);
+                    return Ok(Some(v));
                 } else {
                     return Err(syn::Error::new(
                         name_value.lit.span(),
-                        "Packet IDs must be integers",
+                        format!("{} must be an integer", name),
                     ));
                 }
             }
         }
     }
 
-    Err(syn::Error::new(global_span, "Packet ID must be supplied"))
+    Ok(None)
 }
 
 // Add a bound `T: HeapSize` to every type parameter T.
@@ -145,41 +564,86 @@ fn add_trait_bounds(mut generics: syn::Generics) -> syn::Generics {
     generics
 }
 
-fn sum_and_writes(data: &Data) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
-    match *data {
-        Data::Struct(ref data) => {
-            match data.fields {
-                Fields::Named(ref fields) => {
-                    let sizes = fields.named.iter().map(|f| {
-                        let name = &f.ident;
-                        // This is a hack because there is no easy way to support both
-                        // SizedDataTypes and DataTypes.
-                        quote_spanned! {f.span()=>
-                            self.#name.size()
-                        }
-                    });
-
-                    let writes = fields.named.iter().map(|f| {
-                        let name = &f.ident;
-
-                        quote_spanned! {f.span()=>
-                            self.#name.write_to(&mut data);
-                        }
-                    });
-
-                    let sum = quote! {
-                        0 #(+ #sizes)*
-                    };
-                    let writes = quote! {
-                        #(#writes)*
-                    };
-
-                    (sum.into(), writes.into())
-                }
-                Fields::Unit => (quote!(0), quote!()),
-                Fields::Unnamed(_) => unimplemented!(),
-            }
+fn sum_and_writes(fields: &Fields) -> (TokenStream2, TokenStream2) {
+    let members: Vec<syn::Member> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| match f.ident {
+            Some(ref ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(i.into()),
+        })
+        .collect();
+
+    let sizes = fields.iter().zip(&members).map(|(f, member)| {
+        // This is a hack because there is no easy way to support both
+        // SizedDataTypes and DataTypes.
+        quote_spanned! {f.span()=>
+            self.#member.size()
+        }
+    });
+
+    let writes = fields.iter().zip(&members).map(|(f, member)| {
+        quote_spanned! {f.span()=>
+            self.#member.write_to(&mut data);
+        }
+    });
+
+    let sum = quote! {
+        0 #(+ #sizes)*
+    };
+    let writes = quote! {
+        #(#writes)*
+    };
+
+    (sum, writes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(input: DeriveInput) -> Result<Vec<i32>, syn::Error> {
+        let discriminant = Discriminant::from_attrs(&input.attrs)?;
+        match input.data {
+            Data::Enum(ref data) => variant_values(data, discriminant),
+            _ => panic!("not an enum"),
         }
-        Data::Enum(_) | Data::Union(_) => unimplemented!(),
+    }
+
+    #[test]
+    fn values_follow_the_last_variant() {
+        let input = parse_quote! {
+            #[discriminant(Byte)]
+            enum Example {
+                A,
+                #[value = 5]
+                B,
+                C,
+                D = 127,
+            }
+        };
+
+        assert_eq!(values(input).unwrap(), vec![0, 5, 6, 127]);
+    }
+
+    #[test]
+    fn values_must_fit_the_discriminant() {
+        let byte = parse_quote! {
+            #[discriminant(Byte)]
+            enum Example {
+                #[value = 200]
+                A,
+            }
+        };
+        let unsigned_byte = parse_quote! {
+            #[discriminant(UnsignedByte)]
+            enum Example {
+                A = 255,
+                B,
+            }
+        };
+
+        assert!(values(byte).is_err());
+        assert!(values(unsigned_byte).is_err());
     }
 }
@@ -0,0 +1,80 @@
+//! Compares the in place CFB8 cipher used by the codec with OpenSSL's, which
+//! has to write into a separate buffer.
+//!
+//! Run with `cargo bench --bench cipher`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use openssl::symm::{Cipher, Crypter, Mode};
+
+// The server is only a binary, so the module is pulled in directly. Its tests
+// come along too but never run here.
+#[path = "../src/protocol/cipher.rs"]
+#[allow(dead_code, unused_imports)]
+mod cipher;
+
+use cipher::Cfb8;
+
+const KEY: [u8; 16] = [0x5a; 16];
+
+/// Typical packet sizes, from keep alives up to chunk data.
+const SIZES: [usize; 4] = [16, 256, 4096, 65536];
+
+fn encrypt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encrypt");
+
+    for &size in SIZES.iter() {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("in place", size), &size, |b, &size| {
+            let mut cipher = Cfb8::new(&KEY, &KEY).unwrap();
+            let mut data = vec![0x42; size];
+            b.iter(|| cipher.encrypt(&mut data));
+        });
+
+        group.bench_with_input(BenchmarkId::new("openssl", size), &size, |b, &size| {
+            let mut crypter =
+                Crypter::new(Cipher::aes_128_cfb8(), Mode::Encrypt, &KEY, Some(&KEY)).unwrap();
+            let data = vec![0x42; size];
+            // What the codec used to do for each packet
+            b.iter(|| {
+                let mut out = vec![0; size + 16];
+                let len = crypter.update(&data, &mut out).unwrap();
+                out.truncate(len);
+                out
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn decrypt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decrypt");
+
+    for &size in SIZES.iter() {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("in place", size), &size, |b, &size| {
+            let mut cipher = Cfb8::new(&KEY, &KEY).unwrap();
+            let mut data = vec![0x42; size];
+            b.iter(|| cipher.decrypt(&mut data));
+        });
+
+        group.bench_with_input(BenchmarkId::new("openssl", size), &size, |b, &size| {
+            let mut crypter =
+                Crypter::new(Cipher::aes_128_cfb8(), Mode::Decrypt, &KEY, Some(&KEY)).unwrap();
+            let data = vec![0x42; size];
+            b.iter(|| {
+                let mut out = vec![0; size + 16];
+                let len = crypter.update(&data, &mut out).unwrap();
+                out.truncate(len);
+                out
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, encrypt, decrypt);
+criterion_main!(benches);
@@ -0,0 +1,400 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::api::GameProfile;
+use crate::protocol::data_types::Chat;
+use crate::protocol::proxy_protocol::canonical_ip;
+
+/// The format of dates in the ban lists, same as vanilla.
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+/// A ban as stored in `banned-players.json` and `banned-ips.json`. Every
+/// field is optional, vanilla fills in defaults for any that are missing.
+#[derive(Deserialize)]
+struct BanFields {
+    created: Option<String>,
+    source: Option<String>,
+    expires: Option<String>,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PlayerBanEntry {
+    uuid: Uuid,
+    #[serde(flatten)]
+    ban: BanFields,
+}
+
+#[derive(Deserialize)]
+struct IpBanEntry {
+    ip: IpAddr,
+    #[serde(flatten)]
+    ban: BanFields,
+}
+
+#[derive(Deserialize)]
+struct WhitelistEntry {
+    uuid: Uuid,
+}
+
+/// Why, by whom and until when a player or address is banned.
+#[derive(Clone, Debug)]
+pub struct Ban {
+    pub reason: String,
+    /// Who made the ban, such as an operator's name or "Server".
+    pub source: String,
+    pub created: Option<DateTime<FixedOffset>>,
+    /// None for a ban that lasts forever.
+    pub expires: Option<DateTime<FixedOffset>>,
+}
+
+impl Ban {
+    fn from_fields(fields: BanFields) -> Ban {
+        Ban {
+            reason: fields
+                .reason
+                .unwrap_or_else(|| "Banned by an operator.".to_string()),
+            source: fields.source.unwrap_or_else(|| "(Unknown)".to_string()),
+            created: fields.created.as_deref().and_then(parse_date),
+            // Vanilla treats a date it can't read as forever too
+            expires: match fields.expires.as_deref() {
+                None | Some("forever") => None,
+                Some(expires) => parse_date(expires),
+            },
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now)
+    }
+
+    /// Who made the ban and when, for the log.
+    fn origin(&self) -> String {
+        match self.created {
+            Some(created) => format!("{} on {}", self.source, created.format(DATE_FORMAT)),
+            None => self.source.clone(),
+        }
+    }
+
+    /// The message a client is kicked with, same as vanilla. `key` is the
+    /// translation for the kind of ban.
+    fn disconnect_reason(&self, key: &str) -> Chat {
+        let mut message = json!({ "translate": key, "with": [self.reason] });
+
+        if let Some(expires) = self.expires {
+            message["extra"] = json!([{
+                "translate": "multiplayer.disconnect.banned.expiration",
+                "with": [expires.format(DATE_FORMAT).to_string()],
+            }]);
+        }
+
+        Chat::new(message.to_string())
+    }
+}
+
+fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    match DateTime::parse_from_str(date, DATE_FORMAT) {
+        Ok(date) => Some(date),
+        Err(_) => {
+            warn!("Could not read ban date {:?}", date);
+            None
+        }
+    }
+}
+
+/// Who can join a server, from vanilla's `banned-players.json`,
+/// `banned-ips.json` and `whitelist.json`. Each list is shared with any virtual
+/// host that doesn't have its own.
+#[derive(Clone, Default)]
+pub struct AccessLists {
+    banned_players: Arc<HashMap<Uuid, Ban>>,
+    banned_ips: Arc<HashMap<IpAddr, Ban>>,
+    whitelist: Arc<HashSet<Uuid>>,
+}
+
+impl AccessLists {
+    /// Loads the lists in `dir`. Any that don't exist yet are created empty,
+    /// like vanilla does.
+    pub fn load(dir: &Path) -> Result<AccessLists> {
+        let defaults = AccessLists::default();
+        let lists = defaults.load_overrides(dir)?;
+
+        for name in ["banned-players.json", "banned-ips.json", "whitelist.json"].iter() {
+            let path = dir.join(name);
+            if !path.exists() {
+                fs::write(&path, "[]\n")
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+            }
+        }
+
+        Ok(lists)
+    }
+
+    /// Loads the lists a virtual host has in `dir`, sharing these for any it
+    /// doesn't have.
+    pub fn load_overrides(&self, dir: &Path) -> Result<AccessLists> {
+        let banned_players = match read_list::<PlayerBanEntry>(&dir.join("banned-players.json"))? {
+            Some(entries) => Arc::new(
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.uuid, Ban::from_fields(entry.ban)))
+                    .collect(),
+            ),
+            None => self.banned_players.clone(),
+        };
+
+        let banned_ips = match read_list::<IpBanEntry>(&dir.join("banned-ips.json"))? {
+            Some(entries) => Arc::new(
+                entries
+                    .into_iter()
+                    .map(|entry| (entry.ip, Ban::from_fields(entry.ban)))
+                    .collect(),
+            ),
+            None => self.banned_ips.clone(),
+        };
+
+        let whitelist = match read_list::<WhitelistEntry>(&dir.join("whitelist.json"))? {
+            Some(entries) => Arc::new(entries.into_iter().map(|entry| entry.uuid).collect()),
+            None => self.whitelist.clone(),
+        };
+
+        Ok(AccessLists {
+            banned_players,
+            banned_ips,
+            whitelist,
+        })
+    }
+
+    /// Why a player can't join, or None if they can. The checks are made in
+    /// the same order as vanilla, so a banned player is told they're banned
+    /// even if they aren't whitelisted either.
+    pub fn refusal(
+        &self,
+        profile: &GameProfile,
+        address: Option<IpAddr>,
+        whitelist: bool,
+        now: DateTime<Utc>,
+    ) -> Option<Chat> {
+        if let Some(ban) = self.banned_players.get(&profile.uuid) {
+            if !ban.is_expired(now) {
+                info!("{} is banned by {}", profile.name, ban.origin());
+                return Some(ban.disconnect_reason("multiplayer.disconnect.banned.reason"));
+            }
+        }
+
+        if whitelist && !self.whitelist.contains(&profile.uuid) {
+            return Some(Chat::translate(
+                "multiplayer.disconnect.not_whitelisted",
+                &[],
+            ));
+        }
+
+        if let Some((address, ban)) =
+            address.and_then(|address| self.banned_ips.get_key_value(&canonical_ip(address)))
+        {
+            if !ban.is_expired(now) {
+                info!("{} is banned by {}", address, ban.origin());
+                return Some(ban.disconnect_reason("multiplayer.disconnect.banned_ip.reason"));
+            }
+        }
+
+        None
+    }
+}
+
+/// Reads one of the JSON lists, or None if the file doesn't exist.
+fn read_list<T: DeserializeOwned>(path: &Path) -> Result<Option<Vec<T>>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let entries: Vec<T> = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid list in {}", path.display()))?;
+    info!("Loaded {} entries from {}", entries.len(), path.display());
+
+    Ok(Some(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notch() -> GameProfile {
+        GameProfile {
+            uuid: Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap(),
+            name: "Notch".to_string(),
+            properties: Vec::new(),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_str("2020-06-01 00:00:00 +0000", DATE_FORMAT)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcserver-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reads_vanilla_ban_lists() {
+        let dir = temp_dir("bans");
+        fs::write(
+            dir.join("banned-players.json"),
+            r#"[{
+                "uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5",
+                "name": "Notch",
+                "created": "2020-01-01 12:00:00 +0000",
+                "source": "jeb_",
+                "expires": "2020-12-25 00:00:00 +0100",
+                "reason": "Griefing"
+            }]"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("banned-ips.json"),
+            r#"[{"ip": "203.0.113.7", "expires": "forever"}]"#,
+        )
+        .unwrap();
+
+        let lists = AccessLists::load(&dir).unwrap();
+        // Missing lists are created empty
+        assert_eq!(
+            fs::read_to_string(dir.join("whitelist.json")).unwrap(),
+            "[]\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let ban = &lists.banned_players[&notch().uuid];
+        assert_eq!(ban.reason, "Griefing");
+        assert_eq!(ban.source, "jeb_");
+        assert!(ban.created.is_some());
+
+        let reason = lists.refusal(&notch(), None, false, now()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(reason.as_str()).unwrap(),
+            json!({
+                "translate": "multiplayer.disconnect.banned.reason",
+                "with": ["Griefing"],
+                "extra": [{
+                    "translate": "multiplayer.disconnect.banned.expiration",
+                    "with": ["2020-12-25 00:00:00 +0100"],
+                }],
+            })
+        );
+
+        // Expired bans are ignored
+        let later = now() + chrono::Duration::days(365);
+        assert!(lists.refusal(&notch(), None, false, later).is_none());
+
+        let ip_ban = &lists.banned_ips[&"203.0.113.7".parse().unwrap()];
+        assert_eq!(ip_ban.reason, "Banned by an operator.");
+        assert!(ip_ban.expires.is_none());
+        let address = Some("203.0.113.7".parse().unwrap());
+        let reason = lists.refusal(&notch(), address, false, later).unwrap();
+        assert!(reason
+            .as_str()
+            .contains("multiplayer.disconnect.banned_ip.reason"));
+    }
+
+    #[test]
+    fn ip_bans_match_mapped_addresses() {
+        let mut banned_ips = HashMap::new();
+        banned_ips.insert(
+            "203.0.113.7".parse().unwrap(),
+            Ban::from_fields(BanFields {
+                created: None,
+                source: None,
+                expires: None,
+                reason: None,
+            }),
+        );
+        let lists = AccessLists {
+            banned_ips: Arc::new(banned_ips),
+            ..AccessLists::default()
+        };
+
+        // How a dual stack listener sees an IPv4 client
+        let mapped = Some("::ffff:203.0.113.7".parse().unwrap());
+        assert!(lists.refusal(&notch(), mapped, false, now()).is_some());
+
+        let other = Some("::ffff:203.0.113.8".parse().unwrap());
+        assert!(lists.refusal(&notch(), other, false, now()).is_none());
+    }
+
+    #[test]
+    fn whitelist_is_only_checked_when_enabled() {
+        let dir = temp_dir("whitelist");
+        fs::write(
+            dir.join("whitelist.json"),
+            r#"[{"uuid": "853c80ef-3c37-49fd-aa49-938b674adae6", "name": "jeb_"}]"#,
+        )
+        .unwrap();
+
+        let lists = AccessLists::default().load_overrides(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(lists.refusal(&notch(), None, false, now()).is_none());
+        let reason = lists.refusal(&notch(), None, true, now()).unwrap();
+        assert!(reason
+            .as_str()
+            .contains("multiplayer.disconnect.not_whitelisted"));
+
+        let jeb = GameProfile {
+            uuid: Uuid::parse_str("853c80ef-3c37-49fd-aa49-938b674adae6").unwrap(),
+            name: "jeb_".to_string(),
+            properties: Vec::new(),
+        };
+        assert!(lists.refusal(&jeb, None, true, now()).is_none());
+    }
+
+    #[test]
+    fn hosts_share_lists_they_do_not_have() {
+        let dir = temp_dir("host-lists");
+        fs::write(
+            dir.join("whitelist.json"),
+            r#"[{"uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5", "name": "Notch"}]"#,
+        )
+        .unwrap();
+
+        let mut banned_players = HashMap::new();
+        banned_players.insert(
+            notch().uuid,
+            Ban::from_fields(BanFields {
+                created: None,
+                source: None,
+                expires: None,
+                reason: None,
+            }),
+        );
+        let default = AccessLists {
+            banned_players: Arc::new(banned_players),
+            ..AccessLists::default()
+        };
+
+        let host = default.load_overrides(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(Arc::ptr_eq(&host.banned_players, &default.banned_players));
+        assert!(host.whitelist.contains(&notch().uuid));
+        assert!(default.whitelist.is_empty());
+    }
+}
@@ -1,32 +1,306 @@
-use anyhow::{anyhow, Context, Result};
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
 use num_bigint::BigInt;
+use openssl::hash::{hash, MessageDigest};
 use openssl::sha::Sha1;
-use reqwest::Client;
-use serde_json::Value;
-use uuid::Uuid;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use uuid::{Builder, Uuid, Variant, Version};
+
+/// The base URL of Mojang's session server.
+pub const MOJANG_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+
+/// A player's game profile as returned by the session server.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GameProfile {
+    #[serde(rename = "id")]
+    pub uuid: Uuid,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<ProfileProperty>,
+}
+
+/// A signed property on a game profile, such as the player's skin textures.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl GameProfile {
+    /// The profile given to players in offline mode, which has the vanilla
+    /// offline UUID and no properties.
+    pub fn offline(username: &str) -> Result<GameProfile> {
+        Ok(GameProfile {
+            uuid: offline_uuid(username)?,
+            name: username.to_string(),
+            properties: Vec::new(),
+        })
+    }
+}
+
+/// Returned by an [`Authenticator`] when the session server says the player
+/// hasn't joined, as opposed to the session server being unreachable.
+#[derive(Debug)]
+pub struct UnverifiedUsername(pub String);
+
+impl fmt::Display for UnverifiedUsername {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to verify username {}", self.0)
+    }
+}
+
+impl Error for UnverifiedUsername {}
 
-pub async fn authenticate(username: &str, shared_secret: &[u8], public_key: &[u8]) -> Result<Uuid> {
+/// Checks that a player logging in owns the account they claim to.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// Verifies that `username` has joined the server identified by
+    /// `server_hash` (see [`server_hash`]) and returns their profile. `ip` is
+    /// the address the player connected from, if it is known.
+    ///
+    /// Fails with [`UnverifiedUsername`] if the player couldn't be verified.
+    async fn authenticate(
+        &self,
+        username: &str,
+        server_hash: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<GameProfile>;
+}
+
+/// Computes the server ID hash the client and server both send to the session
+/// server. This is Minecraft's unusual signed hexadecimal SHA-1 digest.
+pub fn server_hash(shared_secret: &[u8], public_key: &[u8]) -> String {
     let mut hasher = Sha1::new();
     hasher.update(b"");
     hasher.update(shared_secret);
     hasher.update(public_key);
     let hash = hasher.finish();
 
-    let hex = format!("{:x}", BigInt::from_signed_bytes_be(&hash));
+    format!("{:x}", BigInt::from_signed_bytes_be(&hash))
+}
+
+/// Authenticates players against Mojang's `hasJoined` endpoint, or anything
+/// else that speaks the same API.
+pub struct MojangAuthenticator {
+    client: Client,
+    has_joined_url: String,
+    prevent_proxy_connections: bool,
+}
+
+impl MojangAuthenticator {
+    /// Creates an authenticator that talks to the session server at
+    /// `base_url`, giving up on requests that take longer than `timeout`. When
+    /// `prevent_proxy_connections` is set the player's IP is sent along so the
+    /// session server can check it matches the one the client joined from.
+    pub fn new(
+        base_url: &str,
+        timeout: Duration,
+        prevent_proxy_connections: bool,
+    ) -> Result<MojangAuthenticator> {
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Failed to build HTTP client")?;
+
+        Ok(MojangAuthenticator {
+            client,
+            has_joined_url: format!(
+                "{}/session/minecraft/hasJoined",
+                base_url.trim_end_matches('/')
+            ),
+            prevent_proxy_connections,
+        })
+    }
+}
+
+#[async_trait]
+impl Authenticator for MojangAuthenticator {
+    async fn authenticate(
+        &self,
+        username: &str,
+        server_hash: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<GameProfile> {
+        let mut query = vec![
+            ("username", username.to_string()),
+            ("serverId", server_hash.to_string()),
+        ];
+
+        if let (true, Some(ip)) = (self.prevent_proxy_connections, ip) {
+            query.push(("ip", ip.to_string()));
+        }
+
+        let result = self
+            .client
+            .get(&self.has_joined_url)
+            .query(&query)
+            .send()
+            .await
+            .context("Failed to query Mojang API")?;
+
+        // The session server answers with an empty body when the player hasn't
+        // joined.
+        if result.status() == StatusCode::NO_CONTENT {
+            return Err(UnverifiedUsername(username.to_string()).into());
+        }
+
+        result
+            .error_for_status()?
+            .json::<GameProfile>()
+            .await
+            .context("Malformed response JSON")
+    }
+}
+
+/// Generates the UUID vanilla servers give players in offline mode: a version 3
+/// (MD5) UUID of the string `OfflinePlayer:<username>`.
+pub fn offline_uuid(username: &str) -> Result<Uuid> {
+    let digest = hash(
+        MessageDigest::md5(),
+        format!("OfflinePlayer:{}", username).as_bytes(),
+    )?;
+
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest);
+
+    Ok(Builder::from_bytes(bytes)
+        .set_variant(Variant::RFC4122)
+        .set_version(Version::Md5)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// Starts a stand-in session server that answers a single request with the
+    /// given status line and body. Resolves to the request line it received.
+    async fn stand_in_session_server(
+        status: &'static str,
+        body: &'static str,
+    ) -> (SocketAddr, JoinHandle<String>) {
+        let mut listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+
+            let request = String::from_utf8(request).unwrap();
+            request.lines().next().unwrap().to_string()
+        });
+
+        (address, handle)
+    }
+
+    #[test]
+    fn offline_uuid_matches_vanilla() {
+        assert_eq!(
+            offline_uuid("Notch").unwrap(),
+            Uuid::parse_str("b50ad385-829d-3141-a216-7e7d7539ba7f").unwrap()
+        );
+    }
+
+    #[test]
+    fn server_hash_matches_vanilla() {
+        // From the wiki.vg protocol encryption page
+        assert_eq!(
+            server_hash(b"Notch", b""),
+            "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48"
+        );
+        assert_eq!(
+            server_hash(b"jeb_", b""),
+            "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1"
+        );
+        assert_eq!(
+            server_hash(b"sim", b"on"),
+            "88e16a1019277b15d58faf0541e11910eb756f6"
+        );
+    }
+
+    #[tokio::test]
+    async fn authenticates_against_stand_in() {
+        let (address, request) = stand_in_session_server(
+            "200 OK",
+            r#"{
+                "id": "069a79f444e94726a5befca90e38aaf5",
+                "name": "Notch",
+                "properties": [{"name": "textures", "value": "e30=", "signature": "c2ln"}]
+            }"#,
+        )
+        .await;
+
+        let authenticator = MojangAuthenticator::new(
+            &format!("http://{}/", address),
+            Duration::from_secs(5),
+            true,
+        )
+        .unwrap();
+
+        let profile = authenticator
+            .authenticate("notch", "abc", Some(IpAddr::V4(Ipv4Addr::LOCALHOST)))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            profile.uuid,
+            Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        );
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(profile.properties.len(), 1);
+        assert_eq!(profile.properties[0].name, "textures");
+        assert_eq!(profile.properties[0].value, "e30=");
+        assert_eq!(profile.properties[0].signature.as_deref(), Some("c2ln"));
+        assert_eq!(
+            request.await.unwrap(),
+            "GET /session/minecraft/hasJoined?username=notch&serverId=abc&ip=127.0.0.1 HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_player_that_has_not_joined() {
+        let (address, _request) = stand_in_session_server("204 No Content", "").await;
 
-    let client = Client::new();
-    let result = client
-        .get("https://sessionserver.mojang.com/session/minecraft/hasJoined")
-        .query(&[("username", username), ("serverId", &hex)])
-        .send()
-        .await
-        .context("Failed to query Mojang API")?;
+        let authenticator = MojangAuthenticator::new(
+            &format!("http://{}", address),
+            Duration::from_secs(5),
+            false,
+        )
+        .unwrap();
 
-    let data = result.json::<Value>().await?;
+        let err = authenticator
+            .authenticate("Notch", "abc", None)
+            .await
+            .unwrap_err();
 
-    Ok(Uuid::parse_str(
-        data["id"]
-            .as_str()
-            .ok_or_else(|| anyhow!("Malformed response JSON"))?,
-    )?)
+        assert!(err.is::<UnverifiedUsername>());
+    }
 }
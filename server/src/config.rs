@@ -0,0 +1,611 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+
+use crate::api::MOJANG_SESSION_SERVER;
+use crate::protocol::connection::{AuthMode, UnknownPacketPolicy};
+use crate::protocol::forwarding::ForwardingMode;
+use crate::protocol::key::{MAX_KEY_SIZE, MIN_KEY_SIZE};
+use crate::protocol::proxy_protocol::Cidr;
+use crate::state::GameMode;
+
+/// The server configuration, loaded from a vanilla compatible
+/// `server.properties` file.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The address to bind to, None binds to every interface.
+    pub server_ip: Option<IpAddr>,
+    pub server_port: u16,
+    pub motd: String,
+    pub max_players: usize,
+    /// Whether players are authenticated with the session server.
+    pub online_mode: bool,
+    /// Whether the player's IP is sent to the session server so it can check
+    /// it matches the one they joined from.
+    pub prevent_proxy_connections: bool,
+    pub view_distance: u8,
+    pub gamemode: GameMode,
+    pub hardcore: bool,
+    /// Whether only players in `whitelist.json` can join.
+    pub white_list: bool,
+    /// A number, or any other text to be hashed into one. Empty picks a random
+    /// seed.
+    pub level_seed: String,
+    pub level_type: String,
+    /// Packets at least this many bytes long are compressed, None disables
+    /// compression.
+    pub network_compression_threshold: Option<usize>,
+
+    // The options from here on aren't in vanilla's server.properties.
+    /// Whether connections are still encrypted when `online_mode` is off.
+    pub offline_encryption: bool,
+    /// The base URL of the session server.
+    pub session_server: String,
+    /// How long to wait on the session server before failing a login.
+    pub session_server_timeout: Duration,
+    /// What to do with play packets the server doesn't recognize.
+    pub unknown_packet_policy: UnknownPacketPolicy,
+    /// A PEM file the server's RSA key is loaded from, or saved to if it
+    /// doesn't exist. None generates a new key every start.
+    pub rsa_key_file: Option<PathBuf>,
+    /// The size in bits of newly generated keys.
+    pub rsa_key_size: u32,
+    /// How a proxy in front of the server forwards player details. Players are
+    /// then authenticated by the proxy rather than the server.
+    pub forwarding_mode: ForwardingMode,
+    /// The secret shared with a Velocity proxy, which signs the player details
+    /// it forwards.
+    pub forwarding_secret: String,
+    /// Whether connections from `proxy_protocol_trusted` addresses start with a
+    /// PROXY protocol header giving the client's real address, as sent by load
+    /// balancers like HAProxy.
+    pub proxy_protocol: bool,
+    /// The addresses load balancers connect from. Connections from anywhere
+    /// else are taken to come straight from the client.
+    pub proxy_protocol_trusted: Vec<Cidr>,
+    /// How long an address has to wait between logins, None to let it log in as
+    /// often as it likes. Set in milliseconds, with a negative value or zero
+    /// turning it off, like Bukkit's option. Ignored behind a forwarding proxy.
+    pub connection_throttle: Option<Duration>,
+    /// The most connections an address can have open at once, None for no
+    /// limit. Ignored behind a forwarding proxy.
+    pub max_connections_per_ip: Option<usize>,
+    /// How long a client has to finish logging in, or to finish pinging, once
+    /// it connects.
+    pub login_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            server_ip: None,
+            server_port: 25565,
+            motd: "A Minecraft Server".to_string(),
+            max_players: 20,
+            online_mode: true,
+            offline_encryption: false,
+            prevent_proxy_connections: false,
+            session_server: MOJANG_SESSION_SERVER.to_string(),
+            session_server_timeout: Duration::from_secs(10),
+            view_distance: 10,
+            gamemode: GameMode::Survival,
+            hardcore: false,
+            white_list: false,
+            level_seed: String::new(),
+            level_type: "default".to_string(),
+            network_compression_threshold: Some(256),
+            unknown_packet_policy: UnknownPacketPolicy::Skip,
+            rsa_key_file: None,
+            rsa_key_size: 1024,
+            forwarding_mode: ForwardingMode::None,
+            forwarding_secret: String::new(),
+            proxy_protocol: false,
+            proxy_protocol_trusted: vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+            connection_throttle: Some(Duration::from_millis(4000)),
+            max_connections_per_ip: Some(8),
+            login_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from the properties file at `path`. Any missing
+    /// options are filled in with their defaults and written back to the file,
+    /// creating it if it doesn't exist yet.
+    pub fn load_or_create(path: &Path) -> Result<Config> {
+        let mut properties = match fs::read_to_string(path) {
+            Ok(contents) => parse_properties(&contents),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("{} not found, writing defaults", path.display());
+                BTreeMap::new()
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        let config = Config::from_properties(&properties)
+            .with_context(|| format!("Invalid value in {}", path.display()))?;
+
+        // Unknown keys are kept around so they survive being written back.
+        properties.extend(config.to_properties());
+        fs::write(path, write_properties(&properties))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    /// Loads a config that only sets some options from the properties file at
+    /// `path`, taking the rest from this one. Nothing is written back, and a
+    /// missing file just gives a copy of this config.
+    pub fn load_overrides(&self, path: &Path) -> Result<Config> {
+        let mut properties = self.to_properties();
+
+        match fs::read_to_string(path) {
+            Ok(contents) => properties.extend(parse_properties(&contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+
+        Config::from_properties(&properties)
+            .with_context(|| format!("Invalid value in {}", path.display()))
+    }
+
+    fn from_properties(properties: &BTreeMap<String, String>) -> Result<Config> {
+        let defaults = Config::default();
+
+        let server_ip = match properties.get("server-ip").map(|s| s.trim()) {
+            None | Some("") => None,
+            Some(ip) => Some(parse_value("server-ip", ip)?),
+        };
+
+        // Vanilla uses a negative threshold to turn compression off.
+        let network_compression_threshold = match properties.get("network-compression-threshold") {
+            None => defaults.network_compression_threshold,
+            Some(value) => {
+                let threshold: i32 = parse_value("network-compression-threshold", value)?;
+                if threshold < 0 {
+                    None
+                } else {
+                    Some(threshold as usize)
+                }
+            }
+        };
+
+        let get = |key: &str| properties.get(key).map(|s| s.as_str());
+
+        let connection_throttle = match get("connection-throttle") {
+            None => defaults.connection_throttle,
+            Some(value) => {
+                let millis: i64 = parse_value("connection-throttle", value)?;
+                if millis <= 0 {
+                    None
+                } else {
+                    Some(Duration::from_millis(millis as u64))
+                }
+            }
+        };
+
+        let max_connections_per_ip = match get("max-connections-per-ip") {
+            None => defaults.max_connections_per_ip,
+            Some(value) => {
+                let max: i32 = parse_value("max-connections-per-ip", value)?;
+                if max <= 0 {
+                    None
+                } else {
+                    Some(max as usize)
+                }
+            }
+        };
+
+        let rsa_key_file = match get("rsa-key-file").map(|s| s.trim()) {
+            None | Some("") => None,
+            Some(path) => Some(PathBuf::from(path)),
+        };
+
+        let rsa_key_size = parse_or(get("rsa-key-size"), "rsa-key-size", defaults.rsa_key_size)?;
+        if !(MIN_KEY_SIZE..=MAX_KEY_SIZE).contains(&rsa_key_size) {
+            return Err(anyhow!(
+                "rsa-key-size must be between {} and {}",
+                MIN_KEY_SIZE,
+                MAX_KEY_SIZE
+            ));
+        }
+
+        let forwarding_mode = parse_or(
+            get("forwarding-mode"),
+            "forwarding-mode",
+            defaults.forwarding_mode,
+        )?;
+        let forwarding_secret =
+            get("forwarding-secret").map_or(defaults.forwarding_secret, str::to_string);
+        if forwarding_mode == ForwardingMode::Velocity && forwarding_secret.is_empty() {
+            return Err(anyhow!(
+                "forwarding-secret must be set to use velocity forwarding"
+            ));
+        }
+
+        let proxy_protocol_trusted = match get("proxy-protocol-trusted") {
+            None => defaults.proxy_protocol_trusted,
+            Some(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|range| !range.is_empty())
+                .map(|range| parse_value("proxy-protocol-trusted", range))
+                .collect::<Result<_>>()?,
+        };
+
+        Ok(Config {
+            server_ip,
+            server_port: parse_or(get("server-port"), "server-port", defaults.server_port)?,
+            motd: get("motd").map_or(defaults.motd, str::to_string),
+            max_players: parse_or(get("max-players"), "max-players", defaults.max_players)?,
+            online_mode: parse_or(get("online-mode"), "online-mode", defaults.online_mode)?,
+            offline_encryption: parse_or(
+                get("offline-encryption"),
+                "offline-encryption",
+                defaults.offline_encryption,
+            )?,
+            prevent_proxy_connections: parse_or(
+                get("prevent-proxy-connections"),
+                "prevent-proxy-connections",
+                defaults.prevent_proxy_connections,
+            )?,
+            session_server: get("session-server").map_or(defaults.session_server, str::to_string),
+            session_server_timeout: Duration::from_secs(parse_or(
+                get("session-server-timeout"),
+                "session-server-timeout",
+                defaults.session_server_timeout.as_secs(),
+            )?),
+            view_distance: parse_or(
+                get("view-distance"),
+                "view-distance",
+                defaults.view_distance,
+            )?,
+            gamemode: parse_or(get("gamemode"), "gamemode", defaults.gamemode)?,
+            hardcore: parse_or(get("hardcore"), "hardcore", defaults.hardcore)?,
+            white_list: parse_or(get("white-list"), "white-list", defaults.white_list)?,
+            level_seed: get("level-seed").map_or(defaults.level_seed, |s| s.trim().to_string()),
+            level_type: get("level-type").map_or(defaults.level_type, |s| s.trim().to_string()),
+            network_compression_threshold,
+            unknown_packet_policy: parse_or(
+                get("unknown-packet-policy"),
+                "unknown-packet-policy",
+                defaults.unknown_packet_policy,
+            )?,
+            rsa_key_file,
+            rsa_key_size,
+            forwarding_mode,
+            forwarding_secret,
+            proxy_protocol: parse_or(
+                get("proxy-protocol"),
+                "proxy-protocol",
+                defaults.proxy_protocol,
+            )?,
+            proxy_protocol_trusted,
+            connection_throttle,
+            max_connections_per_ip,
+            login_timeout: Duration::from_secs(parse_or(
+                get("login-timeout"),
+                "login-timeout",
+                defaults.login_timeout.as_secs(),
+            )?),
+        })
+    }
+
+    fn to_properties(&self) -> BTreeMap<String, String> {
+        let mut properties = BTreeMap::new();
+
+        let mut set = |key: &str, value: String| {
+            properties.insert(key.to_string(), value);
+        };
+
+        set(
+            "server-ip",
+            self.server_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+        );
+        set("server-port", self.server_port.to_string());
+        set("motd", self.motd.clone());
+        set("max-players", self.max_players.to_string());
+        set("online-mode", self.online_mode.to_string());
+        set("offline-encryption", self.offline_encryption.to_string());
+        set(
+            "prevent-proxy-connections",
+            self.prevent_proxy_connections.to_string(),
+        );
+        set("session-server", self.session_server.clone());
+        set(
+            "session-server-timeout",
+            self.session_server_timeout.as_secs().to_string(),
+        );
+        set("view-distance", self.view_distance.to_string());
+        set("gamemode", self.gamemode.to_string());
+        set("hardcore", self.hardcore.to_string());
+        set("white-list", self.white_list.to_string());
+        set("level-seed", self.level_seed.clone());
+        set("level-type", self.level_type.clone());
+        set(
+            "network-compression-threshold",
+            self.network_compression_threshold
+                .map_or(-1, |threshold| threshold as i64)
+                .to_string(),
+        );
+        set(
+            "unknown-packet-policy",
+            self.unknown_packet_policy.to_string(),
+        );
+        set(
+            "rsa-key-file",
+            self.rsa_key_file
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+        );
+        set("rsa-key-size", self.rsa_key_size.to_string());
+        set("forwarding-mode", self.forwarding_mode.to_string());
+        set("forwarding-secret", self.forwarding_secret.clone());
+        set("proxy-protocol", self.proxy_protocol.to_string());
+        set(
+            "proxy-protocol-trusted",
+            self.proxy_protocol_trusted
+                .iter()
+                .map(Cidr::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        set(
+            "connection-throttle",
+            self.connection_throttle
+                .map_or(-1, |throttle| throttle.as_millis() as i64)
+                .to_string(),
+        );
+        set(
+            "max-connections-per-ip",
+            self.max_connections_per_ip
+                .map_or(-1, |max| max as i64)
+                .to_string(),
+        );
+        set("login-timeout", self.login_timeout.as_secs().to_string());
+
+        properties
+    }
+
+    /// Whether a connection from `address` starts with a PROXY protocol
+    /// header.
+    pub fn expects_proxy_header(&self, address: IpAddr) -> bool {
+        self.proxy_protocol
+            && self
+                .proxy_protocol_trusted
+                .iter()
+                .any(|range| range.contains(address))
+    }
+
+    /// The address the server should listen on.
+    pub fn bind_address(&self) -> SocketAddr {
+        SocketAddr::new(
+            self.server_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            self.server_port,
+        )
+    }
+
+    pub fn auth_mode(&self) -> AuthMode {
+        if self.online_mode {
+            AuthMode::Online
+        } else {
+            AuthMode::Offline {
+                encryption: self.offline_encryption,
+            }
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Bad value {:?} for {}", value, key))
+}
+
+fn parse_or<T: FromStr>(value: Option<&str>, key: &str, default: T) -> Result<T> {
+    match value {
+        Some(value) => parse_value(key, value),
+        None => Ok(default),
+    }
+}
+
+/// Parses the contents of a Java properties file. Handles comments, the `=`,
+/// `:` and whitespace separators, and backslash escapes.
+fn parse_properties(contents: &str) -> BTreeMap<String, String> {
+    let mut properties = BTreeMap::new();
+
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        let mut line = line.trim_start().to_string();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+
+        // A line ending in an odd number of backslashes continues onto the
+        // next line.
+        while line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1 {
+            line.pop();
+            match lines.next() {
+                Some(next) => line.push_str(next.trim_start()),
+                None => break,
+            }
+        }
+
+        // Java strings are UTF-16, so `\u` escapes can contain surrogate pairs.
+        // Keys and values are built up as UTF-16 to handle them.
+        let mut key = Vec::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => key.push(unescape(&mut chars)),
+                '=' | ':' => break,
+                c if c.is_whitespace() => {
+                    // Whitespace can separate the key from the value, with an
+                    // optional `=` or `:` after it.
+                    while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+                        chars.next();
+                    }
+                    if let Some('=') | Some(':') = chars.peek() {
+                        chars.next();
+                    }
+                    break;
+                }
+                c => key.extend(c.encode_utf16(&mut [0; 2]).iter()),
+            }
+        }
+
+        while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+            chars.next();
+        }
+
+        let mut value = Vec::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => value.push(unescape(&mut chars)),
+                c => value.extend(c.encode_utf16(&mut [0; 2]).iter()),
+            }
+        }
+
+        properties.insert(
+            String::from_utf16_lossy(&key),
+            String::from_utf16_lossy(&value),
+        );
+    }
+
+    properties
+}
+
+/// Reads the UTF-16 code unit escaped by a backslash in a properties file.
+fn unescape(chars: &mut impl Iterator<Item = char>) -> u16 {
+    let c = match chars.next() {
+        Some('t') => '\t',
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('f') => '\x0c',
+        Some('u') => {
+            let hex: String = chars.take(4).collect();
+            return u16::from_str_radix(&hex, 16).unwrap_or(0xFFFD);
+        }
+        Some(c) => c,
+        None => '\\',
+    };
+
+    // Anything outside the basic multilingual plane can't be escaped by just a
+    // backslash, so one code unit is always enough.
+    c.encode_utf16(&mut [0; 2])[0]
+}
+
+/// Writes properties out in the same format vanilla does, escaping anything
+/// outside of printable ASCII.
+fn write_properties(properties: &BTreeMap<String, String>) -> String {
+    let mut contents = "#Minecraft server properties\n".to_string();
+
+    for (key, value) in properties {
+        escape(key, true, &mut contents);
+        contents.push('=');
+        escape(value, false, &mut contents);
+        contents.push('\n');
+    }
+
+    contents
+}
+
+fn escape(s: &str, is_key: bool, out: &mut String) {
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\x0c' => out.push_str("\\f"),
+            '=' | ':' | '#' | '!' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' ' if is_key || i == 0 => out.push_str("\\ "),
+            ' '..='~' => out.push(c),
+            c => {
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    out.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_vanilla_properties() {
+        let properties = parse_properties(
+            "#Minecraft server properties\n\
+             #Mon Jan 01 00:00:00 UTC 2020\n\
+             server-ip=\n\
+             server-port=25566\n\
+             motd=\\u00A7aHello\\: World\n\
+             online-mode=false\n\
+             network-compression-threshold=-1\n",
+        );
+
+        let config = Config::from_properties(&properties).unwrap();
+
+        assert_eq!(config.server_ip, None);
+        assert_eq!(config.server_port, 25566);
+        assert_eq!(config.motd, "\u{a7}aHello: World");
+        assert!(!config.online_mode);
+        assert_eq!(config.network_compression_threshold, None);
+        // Missing keys take their defaults
+        assert_eq!(config.max_players, 20);
+    }
+
+    #[test]
+    fn properties_round_trip() {
+        let config = Config {
+            motd: "  \u{a7}cA=B:C #1 \u{1F600}\n".to_string(),
+            server_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            ..Config::default()
+        };
+
+        let written = write_properties(&config.to_properties());
+        let read = Config::from_properties(&parse_properties(&written)).unwrap();
+
+        assert_eq!(read.motd, config.motd);
+        assert_eq!(read.server_ip, config.server_ip);
+        assert_eq!(
+            read.network_compression_threshold,
+            config.network_compression_threshold
+        );
+        assert_eq!(read.proxy_protocol_trusted, config.proxy_protocol_trusted);
+        assert_eq!(read.connection_throttle, config.connection_throttle);
+        assert_eq!(read.max_connections_per_ip, config.max_connections_per_ip);
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let properties = parse_properties("server-port=lots\n");
+
+        assert!(Config::from_properties(&properties).is_err());
+
+        let properties = parse_properties("rsa-key-size=512\n");
+
+        assert!(Config::from_properties(&properties).is_err());
+
+        // Velocity's data can't be trusted without a secret to check it with
+        let properties = parse_properties("forwarding-mode=velocity\nforwarding-secret=\n");
+
+        assert!(Config::from_properties(&properties).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::protocol::forwarding::ForwardingMode;
+use crate::protocol::proxy_protocol::canonical_ip;
+
+/// How many login attempts there are between clearing out the old ones tracked
+/// for throttling, same as Spigot.
+const THROTTLE_CLEANUP_INTERVAL: u64 = 200;
+
+/// Why a connection was turned away.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The address already had as many connections open as it's allowed.
+    TooManyConnections,
+    /// The address tried to log in again too soon.
+    Throttled,
+    /// The client asked to log in but took too long to finish.
+    TimedOut,
+    /// A load balancer sent a bad PROXY protocol header, or didn't send one
+    /// in time.
+    BadProxyHeader,
+}
+
+impl Rejection {
+    const ALL: [Rejection; 4] = [
+        Rejection::TooManyConnections,
+        Rejection::Throttled,
+        Rejection::TimedOut,
+        Rejection::BadProxyHeader,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rejection::TooManyConnections => "too many connections",
+            Rejection::Throttled => "throttled",
+            Rejection::TimedOut => "timed out",
+            Rejection::BadProxyHeader => "bad proxy header",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Limits on how many connections each address can have open and how often
+/// it can log in, shared by every connection on the listener. Also counts the
+/// connections turned away for any reason.
+pub struct ConnectionLimits {
+    /// The most connections an address can have open, None for no limit.
+    max_per_address: Option<usize>,
+    /// How long an address has to wait between logins, None for no limit.
+    throttle: Option<Duration>,
+    open: Mutex<HashMap<IpAddr, usize>>,
+    /// When each address last tried to log in.
+    logins: Mutex<HashMap<IpAddr, Instant>>,
+    /// How many login attempts there have been since the old ones were last
+    /// cleared out. Only changed with `logins` locked.
+    attempts_since_cleanup: AtomicU64,
+    /// Indexed like `Rejection::ALL`.
+    rejected: [AtomicU64; 4],
+}
+
+impl ConnectionLimits {
+    pub fn new(max_per_address: Option<usize>, throttle: Option<Duration>) -> ConnectionLimits {
+        ConnectionLimits {
+            max_per_address,
+            throttle,
+            open: Mutex::new(HashMap::new()),
+            logins: Mutex::new(HashMap::new()),
+            attempts_since_cleanup: AtomicU64::new(0),
+            rejected: Default::default(),
+        }
+    }
+
+    /// The limits set in the config. Behind a forwarding proxy every
+    /// connection comes from the proxy, which is left to apply its own.
+    pub fn from_config(config: &Config) -> ConnectionLimits {
+        if config.forwarding_mode != ForwardingMode::None {
+            return ConnectionLimits::new(None, None);
+        }
+
+        ConnectionLimits::new(config.max_connections_per_ip, config.connection_throttle)
+    }
+
+    /// Counts a connection from `address` as open until the returned handle
+    /// is dropped. Returns None if the address already has too many open.
+    pub fn open(self: &Arc<Self>, address: IpAddr) -> Option<OpenConnection> {
+        let address = canonical_ip(address);
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(address).or_insert(0);
+
+        if let Some(max) = self.max_per_address {
+            if *count >= max {
+                drop(open);
+                self.reject(Rejection::TooManyConnections);
+                return None;
+            }
+        }
+
+        *count += 1;
+
+        Some(OpenConnection {
+            limits: self.clone(),
+            address,
+        })
+    }
+
+    /// Whether `address` can log in now, like vanilla's `connection-throttle`.
+    /// Every attempt restarts the wait, even ones that are turned away, and
+    /// logins from the same machine are never throttled.
+    pub fn allow_login(&self, address: IpAddr, now: Instant) -> bool {
+        let address = canonical_ip(address);
+        let throttle = match self.throttle {
+            Some(throttle) if !address.is_loopback() => throttle,
+            _ => return true,
+        };
+
+        let mut logins = self.logins.lock().unwrap();
+        let throttled = matches!(
+            logins.insert(address, now),
+            Some(last) if now.saturating_duration_since(last) < throttle
+        );
+
+        let attempts = self.attempts_since_cleanup.fetch_add(1, Ordering::Relaxed) + 1;
+        if attempts >= THROTTLE_CLEANUP_INTERVAL {
+            self.attempts_since_cleanup.store(0, Ordering::Relaxed);
+            logins.retain(|_, last| now.saturating_duration_since(*last) < throttle);
+        }
+
+        drop(logins);
+        if throttled {
+            self.reject(Rejection::Throttled);
+        }
+
+        !throttled
+    }
+
+    /// Counts a connection turned away.
+    pub fn reject(&self, reason: Rejection) {
+        let index = Rejection::ALL.iter().position(|&r| r == reason).unwrap();
+        self.rejected[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many connections have been turned away for `reason`.
+    pub fn rejected(&self, reason: Rejection) -> u64 {
+        let index = Rejection::ALL.iter().position(|&r| r == reason).unwrap();
+        self.rejected[index].load(Ordering::Relaxed)
+    }
+
+    /// How many connections have been turned away for any reason.
+    pub fn rejected_total(&self) -> u64 {
+        Rejection::ALL
+            .iter()
+            .map(|&reason| self.rejected(reason))
+            .sum()
+    }
+
+    /// A summary of the connections turned away, for the log.
+    pub fn rejections(&self) -> String {
+        Rejection::ALL
+            .iter()
+            .map(|&reason| format!("{} {}", self.rejected(reason), reason))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Keeps a connection counted against its address while it's open.
+pub struct OpenConnection {
+    limits: Arc<ConnectionLimits>,
+    address: IpAddr,
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        let mut open = self.limits.open.lock().unwrap();
+
+        if let Some(count) = open.get_mut(&self.address) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.address);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_open_connections_per_address() {
+        let limits = Arc::new(ConnectionLimits::new(Some(2), None));
+        let address = "203.0.113.7".parse().unwrap();
+
+        let first = limits.open(address).unwrap();
+        let _second = limits.open(address).unwrap();
+        assert!(limits.open(address).is_none());
+        // Other addresses have their own limit
+        assert!(limits.open("203.0.113.8".parse().unwrap()).is_some());
+
+        drop(first);
+        assert!(limits.open(address).is_some());
+        assert_eq!(limits.rejected(Rejection::TooManyConnections), 1);
+    }
+
+    #[test]
+    fn throttles_logins_like_vanilla() {
+        let limits = ConnectionLimits::new(None, Some(Duration::from_secs(4)));
+        let address = "203.0.113.7".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limits.allow_login(address, start));
+        assert!(!limits.allow_login(address, start + Duration::from_secs(3)));
+        // The throttled attempt restarted the wait
+        assert!(!limits.allow_login(address, start + Duration::from_secs(6)));
+        assert!(limits.allow_login(address, start + Duration::from_secs(11)));
+
+        assert!(limits.allow_login("127.0.0.1".parse().unwrap(), start));
+        assert!(limits.allow_login("127.0.0.1".parse().unwrap(), start));
+
+        assert_eq!(limits.rejected(Rejection::Throttled), 2);
+        assert_eq!(limits.rejected_total(), 2);
+    }
+
+    #[test]
+    fn mapped_addresses_count_as_ipv4() {
+        let limits = Arc::new(ConnectionLimits::new(Some(1), Some(Duration::from_secs(4))));
+        let start = Instant::now();
+
+        let _open = limits.open("203.0.113.7".parse().unwrap()).unwrap();
+        assert!(limits.open("::ffff:203.0.113.7".parse().unwrap()).is_none());
+
+        assert!(limits.allow_login("203.0.113.7".parse().unwrap(), start));
+        assert!(!limits.allow_login("::ffff:203.0.113.7".parse().unwrap(), start));
+
+        // Local logins on a dual stack listener are still never throttled
+        let local = "::ffff:127.0.0.1".parse().unwrap();
+        assert!(limits.allow_login(local, start));
+        assert!(limits.allow_login(local, start));
+    }
+
+    #[test]
+    fn old_logins_are_cleared_out() {
+        let limits = ConnectionLimits::new(None, Some(Duration::from_secs(4)));
+        let start = Instant::now();
+
+        for i in 0..THROTTLE_CLEANUP_INTERVAL - 1 {
+            let address = IpAddr::from([203, 0, 113, i as u8]);
+            assert!(limits.allow_login(address, start + Duration::from_secs(i)));
+        }
+        assert_eq!(limits.logins.lock().unwrap().len(), 199);
+
+        // Attempts are counted even from an address that already tried, which
+        // the map growing wouldn't show. Only the addresses that tried within
+        // the throttle are kept.
+        let later = start + Duration::from_secs(THROTTLE_CLEANUP_INTERVAL);
+        limits.allow_login(IpAddr::from([203, 0, 113, 0]), later);
+        assert_eq!(limits.logins.lock().unwrap().len(), 3);
+    }
+}
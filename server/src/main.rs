@@ -1,28 +1,112 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
-use log::{error, info};
-use openssl::pkey::Private;
-use openssl::rsa;
+use log::{error, info, warn};
 use simple_logger::SimpleLogger;
 use tokio::net::TcpListener;
 use tokio::stream::StreamExt;
+use tokio::time::{self, delay_for, timeout, Instant};
 
 #[macro_use]
 extern crate mcserver_macros;
 
+mod access;
 mod api;
+mod config;
+mod limits;
 mod protocol;
+mod registry;
+mod state;
+mod virtual_host;
 
+use access::AccessLists;
+use api::{Authenticator, MojangAuthenticator};
+use config::Config;
+use limits::{ConnectionLimits, Rejection};
 use protocol::connection::ConnectionHandler;
+use protocol::forwarding::ForwardingMode;
+use protocol::key::ServerKey;
+use protocol::proxy_protocol;
+use state::ServerState;
+use virtual_host::VirtualHosts;
+
+/// How long to wait for connections to disconnect their clients when stopping.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the number of rejected connections is logged.
+const METRICS_INTERVAL: Duration = Duration::from_secs(300);
 
 #[tokio::main]
 async fn main() {
     SimpleLogger::new().init().unwrap();
 
-    let rsa_key = Arc::new(rsa::Rsa::generate(1024).expect("Could not generate server key"));
+    let config = Arc::new(
+        Config::load_or_create(Path::new("server.properties"))
+            .map_err(|e| format!("Could not load server config: {:#}", e))
+            .unwrap(),
+    );
+
+    match config.forwarding_mode {
+        // Anyone can claim to be BungeeCord, Velocity signs what it forwards
+        ForwardingMode::BungeeCord => warn!(
+            "Trusting players forwarded by {}, the server must only be reachable through the proxy",
+            config.forwarding_mode
+        ),
+        ForwardingMode::Velocity => {}
+        ForwardingMode::None if !config.online_mode => {
+            warn!("Running in offline mode, players will not be authenticated")
+        }
+        ForwardingMode::None => {}
+    }
+
+    // Every forwarded connection comes from the proxy, so per-address limits
+    // would only limit the proxy.
+    if config.forwarding_mode != ForwardingMode::None
+        && (config.max_connections_per_ip.is_some() || config.connection_throttle.is_some())
+    {
+        warn!("Ignoring max-connections-per-ip and connection-throttle behind a proxy, it needs to apply its own limits")
+    }
+
+    let favicon = state::load_favicon(Path::new("server-icon.png")).unwrap_or_else(|e| {
+        warn!("Could not load server icon: {:#}", e);
+        None
+    });
+
+    let authenticator: Arc<dyn Authenticator> = Arc::new(
+        MojangAuthenticator::new(
+            &config.session_server,
+            config.session_server_timeout,
+            config.prevent_proxy_connections,
+        )
+        .expect("Could not create authenticator"),
+    );
+
+    let server_key = match &config.rsa_key_file {
+        Some(path) => ServerKey::load_or_generate(path, config.rsa_key_size),
+        None => ServerKey::generate(config.rsa_key_size),
+    };
+    let server_key = Arc::new(
+        server_key
+            .map_err(|e| format!("Could not set up server key: {:#}", e))
+            .unwrap(),
+    );
+
+    let access_lists = AccessLists::load(Path::new("."))
+        .map_err(|e| format!("Could not load ban lists and whitelist: {:#}", e))
+        .unwrap();
 
-    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 25565);
+    let address = config.bind_address();
+    let server_state = Arc::new(ServerState::new(config, favicon, access_lists));
+    let shutdown_state = server_state.clone();
+    let hosts = Arc::new(
+        VirtualHosts::load(Path::new("virtual-hosts"), server_state)
+            .map_err(|e| format!("Could not load virtual hosts: {:#}", e))
+            .unwrap(),
+    );
+    let limits = Arc::new(ConnectionLimits::from_config(hosts.default_host().config()));
+    let metrics_limits = limits.clone();
+    let shutdown_limits = limits.clone();
     let mut listener = TcpListener::bind(address)
         .await
         .map_err(|e| format!("Could not bind to {}: {}", address, e))
@@ -31,14 +115,64 @@ async fn main() {
     let server = async move {
         while let Some(result) = listener.next().await {
             match result {
-                Ok(socket) => {
-                    let peer_addr = socket.peer_addr().unwrap();
-                    info!("Accepted connection from {}", peer_addr);
+                Ok(mut socket) => {
+                    let socket_addr = socket.peer_addr().unwrap();
 
-                    let key_copy = rsa_key.clone();
+                    let hosts = hosts.clone();
+                    let server_key = server_key.clone();
+                    let authenticator = authenticator.clone();
+                    let limits = limits.clone();
                     // Spawn a new task for each connection
                     tokio::spawn(async move {
-                        let connection_handler = ConnectionHandler::new(key_copy, socket);
+                        let config = hosts.default_host().config();
+                        let peer_addr = if config.expects_proxy_header(socket_addr.ip()) {
+                            let header = proxy_protocol::read_header(&mut socket);
+                            match timeout(config.login_timeout, header).await {
+                                Ok(Ok(Some(client_addr))) => {
+                                    info!(
+                                        "Accepted connection from {} through {}",
+                                        client_addr, socket_addr
+                                    );
+                                    client_addr
+                                }
+                                Ok(Ok(None)) => {
+                                    info!("Accepted connection from {}", socket_addr);
+                                    socket_addr
+                                }
+                                Ok(Err(err)) => {
+                                    limits.reject(Rejection::BadProxyHeader);
+                                    warn!("{} - bad PROXY header: {:#}", socket_addr, err);
+                                    return;
+                                }
+                                Err(_) => {
+                                    limits.reject(Rejection::BadProxyHeader);
+                                    warn!("{} - timed out waiting for PROXY header", socket_addr);
+                                    return;
+                                }
+                            }
+                        } else {
+                            info!("Accepted connection from {}", socket_addr);
+                            socket_addr
+                        };
+
+                        // Nothing can be sent before the handshake, so the
+                        // connection is just closed.
+                        let _open = match limits.open(peer_addr.ip()) {
+                            Some(open) => open,
+                            None => {
+                                warn!("{} - too many connections from address", peer_addr);
+                                return;
+                            }
+                        };
+
+                        let connection_handler = ConnectionHandler::new(
+                            hosts,
+                            server_key,
+                            authenticator,
+                            limits,
+                            socket,
+                            Some(peer_addr),
+                        );
 
                         let result = connection_handler.execute().await;
 
@@ -59,5 +193,34 @@ async fn main() {
 
     info!("Server listening on {}", address);
 
-    server.await;
+    // Only logs when something has been rejected since the last time
+    let metrics = async move {
+        let mut interval = time::interval(METRICS_INTERVAL);
+        let mut last_total = 0;
+        loop {
+            interval.tick().await;
+
+            let total = metrics_limits.rejected_total();
+            if total != last_total {
+                info!("Rejected connections: {}", metrics_limits.rejections());
+                last_total = total;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = server => {}
+        _ = metrics => {}
+        _ = tokio::signal::ctrl_c() => {
+            info!("Stopping server");
+            shutdown_state.shutdown();
+
+            let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+            while shutdown_state.connection_count() > 0 && Instant::now() < deadline {
+                delay_for(Duration::from_millis(10)).await;
+            }
+
+            info!("Rejected connections: {}", shutdown_limits.rejections());
+        }
+    }
 }
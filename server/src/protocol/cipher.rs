@@ -0,0 +1,124 @@
+use std::convert::TryInto;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use anyhow::{anyhow, Result};
+
+/// AES-128 in 8 bit cipher feedback mode, the stream cipher used once a
+/// connection is encrypted. Unlike OpenSSL's `Crypter` it works in place, so
+/// the codec doesn't need a second buffer for every packet.
+pub struct Cfb8 {
+    cipher: Aes128,
+    /// The last 16 bytes of ciphertext, which get encrypted to make the next
+    /// byte of keystream.
+    register: [u8; 16],
+}
+
+impl Cfb8 {
+    pub fn new(key: &[u8], iv: &[u8]) -> Result<Cfb8> {
+        let cipher = Aes128::new_from_slice(key)
+            .map_err(|_| anyhow!("Key is {} bytes but must be 16", key.len()))?;
+        let register = iv
+            .try_into()
+            .map_err(|_| anyhow!("IV is {} bytes but must be 16", iv.len()))?;
+
+        Ok(Cfb8 { cipher, register })
+    }
+
+    /// The next byte of keystream.
+    fn keystream(&self) -> u8 {
+        let mut block = GenericArray::from(self.register);
+        self.cipher.encrypt_block(&mut block);
+        block[0]
+    }
+
+    /// Feeds a byte of ciphertext back into the register.
+    fn shift(&mut self, ciphertext: u8) {
+        self.register.copy_within(1.., 0);
+        self.register[15] = ciphertext;
+    }
+
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            *byte ^= self.keystream();
+            self.shift(*byte);
+        }
+    }
+
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            let ciphertext = *byte;
+            *byte ^= self.keystream();
+            self.shift(ciphertext);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::symm::{Cipher, Crypter, Mode};
+
+    use super::*;
+
+    fn openssl(mode: Mode, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut crypter = Crypter::new(Cipher::aes_128_cfb8(), mode, key, Some(key)).unwrap();
+        let mut out = vec![0; data.len() + 16];
+        let len = crypter.update(data, &mut out).unwrap();
+        out.truncate(len);
+        out
+    }
+
+    #[test]
+    fn matches_the_nist_test_vector() {
+        // From NIST SP 800-38A, F.3.7
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        let iv: Vec<u8> = (0..16).collect();
+        let plaintext = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a, 0xae, 0x2d,
+        ];
+        let ciphertext = [
+            0x3b, 0x79, 0x42, 0x4c, 0x9c, 0x0d, 0xd4, 0x36, 0xba, 0xce, 0x9e, 0x0e, 0xd4, 0x58,
+            0x6a, 0x4f, 0x32, 0xb9,
+        ];
+
+        let mut data = plaintext;
+        Cfb8::new(&key, &iv).unwrap().encrypt(&mut data);
+        assert_eq!(data, ciphertext);
+
+        Cfb8::new(&key, &iv).unwrap().decrypt(&mut data);
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    fn matches_openssl_across_calls() {
+        let key = [0x5a; 16];
+        let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let encrypted = openssl(Mode::Encrypt, &key, &data);
+
+        // The cipher is a stream, so splitting the data up makes no difference.
+        let mut encrypter = Cfb8::new(&key, &key).unwrap();
+        let mut decrypter = Cfb8::new(&key, &key).unwrap();
+        let mut buffer = data.clone();
+        for chunk in buffer.chunks_mut(37) {
+            encrypter.encrypt(chunk);
+        }
+        assert_eq!(buffer, encrypted);
+
+        for chunk in buffer.chunks_mut(100) {
+            decrypter.decrypt(chunk);
+        }
+        assert_eq!(buffer, data);
+        assert_eq!(openssl(Mode::Decrypt, &key, &encrypted), data);
+    }
+
+    #[test]
+    fn rejects_bad_key_sizes() {
+        assert!(Cfb8::new(&[0; 32], &[0; 16]).is_err());
+        assert!(Cfb8::new(&[0; 16], &[0; 15]).is_err());
+    }
+}
@@ -1,157 +1,713 @@
 use std::convert::TryInto;
-
-use anyhow::{Context, Error};
-use bytes::BytesMut;
+use std::error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Context, Error};
+use bytes::{Buf, BufMut, BytesMut};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use log::{info, trace};
-use openssl::symm::{Cipher, Crypter, Mode};
 use tokio_util::codec::{Decoder, Encoder};
 
+use crate::protocol::cipher::Cfb8;
 use crate::protocol::data_types::{DataType, DataTypeError, VarInt};
 use crate::protocol::packets::{ClientboundPacket, ServerboundPacket};
 
+/// The largest uncompressed packet the vanilla 1.16 client and server will
+/// accept.
+pub const MAX_UNCOMPRESSED_PACKET_SIZE: usize = 2097152;
+
+/// The most bytes vanilla reads for the length at the start of a frame.
+const MAX_LENGTH_SIZE: usize = 3;
+
+/// The longest frame there can be, 2^21 - 1 bytes, since its length has to fit
+/// in a 3 byte VarInt.
+pub const MAX_PACKET_SIZE: usize = (1 << (7 * MAX_LENGTH_SIZE)) - 1;
+
+/// A frame the client should never have sent. These end the connection.
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    /// The length of the frame takes more than 3 bytes, or is negative.
+    LengthTooWide,
+    /// The frame, or the packet once decompressed, is longer than the most
+    /// allowed in the current state.
+    TooLarge { length: usize, max: usize },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LengthTooWide => write!(f, "Packet length wider than 21 bits"),
+            Self::TooLarge { length, max } => write!(
+                f,
+                "Packet of {} bytes is larger than the maximum of {}",
+                length, max
+            ),
+        }
+    }
+}
+
+impl error::Error for FrameError {}
+
 pub struct ServerboundDecoder {
-    /// An OpenSSL cipher that will be Some when encryption is enabled.
-    decrypter: Option<Crypter>,
-    /// An internal buffer that buffers decrypted packet data for the decoder.
-    buffer: BytesMut,
+    /// The cipher, Some when encryption is enabled.
+    decrypter: Option<Cfb8>,
+    /// How many bytes at the start of the source buffer have already been
+    /// decrypted, by calls that didn't have a whole packet to decode yet.
+    decrypted: usize,
+    /// The compression threshold, Some when compression is enabled.
+    compression_threshold: Option<usize>,
+    /// The longest frame that will be accepted, which depends on the state of
+    /// the connection.
+    max_packet_size: usize,
+    /// The longest a packet can be once it's decompressed, which depends on
+    /// the state too.
+    max_data_length: usize,
 }
 
 impl ServerboundDecoder {
     pub fn new() -> ServerboundDecoder {
         ServerboundDecoder {
             decrypter: None,
-            buffer: BytesMut::new(),
+            decrypted: 0,
+            compression_threshold: None,
+            max_packet_size: MAX_PACKET_SIZE,
+            max_data_length: MAX_UNCOMPRESSED_PACKET_SIZE,
         }
     }
 
+    /// Limits how long packets can be, up to `MAX_PACKET_SIZE` for a frame
+    /// and `MAX_UNCOMPRESSED_PACKET_SIZE` once decompressed. Longer frames are
+    /// rejected as soon as their length arrives, before any of the rest is
+    /// buffered, and compressed packets before they're inflated.
+    pub fn set_max_packet_size(&mut self, max: usize) {
+        self.max_packet_size = max.min(MAX_PACKET_SIZE);
+        self.max_data_length = max.min(MAX_UNCOMPRESSED_PACKET_SIZE);
+    }
+
     pub fn enable_encryption(&mut self, key: &[u8]) -> anyhow::Result<()> {
         info!("decoder enabling encryption");
 
-        self.decrypter = Some(Crypter::new(
-            Cipher::aes_128_cfb8(),
-            Mode::Decrypt,
-            key,
-            Some(key), // Both sides use the shared secret as the Key and IV
-        )?);
+        // Both sides use the shared secret as the Key and IV
+        self.decrypter = Some(Cfb8::new(key, key)?);
+        // Anything the client sent after the Encryption Response is still
+        // buffered as it arrived, so it all needs decrypting.
+        self.decrypted = 0;
 
         Ok(())
     }
+
+    /// Switches the decoder over to the compressed packet format. Packets whose
+    /// uncompressed size is at least `threshold` bytes are expected to be zlib
+    /// compressed.
+    pub fn enable_compression(&mut self, threshold: usize) {
+        info!("decoder enabling compression (threshold {})", threshold);
+
+        self.compression_threshold = Some(threshold);
+    }
+}
+
+/// Reads the body of a packet in the compressed format, decompressing it if
+/// necessary. The returned buffer starts with the packet ID, and is never
+/// longer than `max_length`.
+fn decompress(
+    mut packet_data: BytesMut,
+    threshold: usize,
+    max_length: usize,
+) -> anyhow::Result<BytesMut> {
+    let data_length = VarInt::read_from(&mut packet_data)?.value();
+
+    // A data length of zero means the packet was sent uncompressed. Like
+    // vanilla, packets over the threshold are allowed to be sent this way too.
+    if data_length == 0 {
+        return Ok(packet_data);
+    }
+
+    let data_length: usize = data_length
+        .try_into()
+        .map_err(|_| anyhow!("Bad data length {} in compressed packet", data_length))?;
+
+    if data_length < threshold {
+        return Err(anyhow!(
+            "Compressed packet of {} bytes is below compression threshold {}",
+            data_length,
+            threshold
+        ));
+    }
+
+    if data_length > max_length {
+        return Err(FrameError::TooLarge {
+            length: data_length,
+            max: max_length,
+        }
+        .into());
+    }
+
+    let mut decompressed = Vec::with_capacity(data_length);
+    ZlibDecoder::new(packet_data.as_ref())
+        // Read one extra byte so we can tell if the client lied about the size.
+        .take(data_length as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .context("Failed to decompress packet")?;
+
+    if decompressed.len() != data_length {
+        return Err(anyhow!(
+            "Compressed packet claimed {} bytes but decompressed to {}",
+            data_length,
+            decompressed.len()
+        ));
+    }
+
+    Ok(BytesMut::from(&decompressed[..]))
 }
 
 impl Decoder for ServerboundDecoder {
     type Item = ServerboundPacket;
     type Error = Error;
 
-    fn decode(&mut self, mut src: &mut BytesMut) -> Result<Option<ServerboundPacket>, Error> {
-        // When encryption is disabled it's faster to read from the source
-        // buffer. When encryption is enabled we have to read from our internal
-        // buffer (after decrypting into it).
-        let mut read_from = if let Some(decrypter) = self.decrypter.as_mut() {
-            let start = self.buffer.len();
-            let new_data = src.split();
-
-            // The OpenSSL api doesn't allow for decryption in place.
-            // Unfortunately this means heap allocations for every packet.
-            // TODO some Rust libraries allow in place decryption (how much do we care about
-            // security?
-            self.buffer.resize(new_data.len() + 16 + start, 0);
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<ServerboundPacket>, Error> {
+        // Data is decrypted where it sits in the source buffer, so only the
+        // bytes that arrived since the last call need decrypting.
+        if let Some(decrypter) = self.decrypter.as_mut() {
+            decrypter.decrypt(&mut src[self.decrypted..]);
+        }
 
-            let num_decrypted = decrypter.update(&new_data, &mut self.buffer[start..])?;
+        let packet = self.decode_frame(src);
 
-            self.buffer.truncate(start + num_decrypted);
+        // Whatever is left over has been decrypted.
+        self.decrypted = src.len();
 
-            &mut self.buffer
-        } else {
-            &mut src
-        };
+        packet
+    }
+}
 
-        let packet_length = match VarInt::careful_read_from(&mut read_from) {
-            Ok(v) => v.value() as usize,
-            Err(DataTypeError::OutOfBytes(_)) => {
-                src.reserve(5);
+impl ServerboundDecoder {
+    /// Takes the (decrypted) frame for a packet off the front of the source
+    /// buffer, if all of it has arrived. Nothing is consumed until it has, so a
+    /// frame can arrive over any number of reads.
+    fn decode_frame(&self, src: &mut BytesMut) -> Result<Option<ServerboundPacket>, Error> {
+        let length_bytes = &src[..src.len().min(MAX_LENGTH_SIZE)];
+
+        let (packet_length, header_length) = match VarInt::peek(length_bytes) {
+            Ok((v, size)) => (v.value() as usize, size),
+            Err(DataTypeError::OutOfBytes(_)) if length_bytes.len() < MAX_LENGTH_SIZE => {
+                src.reserve(MAX_LENGTH_SIZE);
                 return Ok(None);
             }
-            Err(e) => return Err(e.into()),
+            Err(_) => return Err(FrameError::LengthTooWide.into()),
         };
 
         trace!("packet length: {} bytes", packet_length);
 
-        if packet_length <= read_from.len() {
+        if packet_length > self.max_packet_size {
+            return Err(FrameError::TooLarge {
+                length: packet_length,
+                max: self.max_packet_size,
+            }
+            .into());
+        }
+
+        let frame_length = header_length + packet_length;
+
+        if frame_length <= src.len() {
             trace!("enough bytes in source buffer");
 
-            let mut packet_data = read_from.split_to(packet_length);
+            src.advance(header_length);
+            let mut packet_data = src.split_to(packet_length);
+
+            if let Some(threshold) = self.compression_threshold {
+                packet_data = decompress(packet_data, threshold, self.max_data_length)?;
+            }
+
             let packet_id = VarInt::read_from(&mut packet_data)?;
 
             trace!("packet ID: {:#04x}", packet_id.value());
 
-            // Reserve space in the buffer for a max size VarInt.
-            src.reserve(5);
+            // Reserve space in the buffer for the next length.
+            src.reserve(MAX_LENGTH_SIZE);
             Ok(Some(ServerboundPacket::new(packet_id.value(), packet_data)))
         } else {
             trace!("not enough bytes in source buffer");
 
-            // Reserve space for the rest of this packet.
-            src.reserve(packet_length);
+            // Reserve space for the rest of this packet, which is safe now
+            // that its length has been checked.
+            src.reserve(frame_length - src.len());
             Ok(None)
         }
     }
 }
 
-pub enum EncoderError {}
-
 pub struct ClientboundEncoder {
-    encrypter: Option<Crypter>,
+    /// The cipher, Some when encryption is enabled.
+    encrypter: Option<Cfb8>,
+    /// The compression threshold, Some when compression is enabled.
+    compression_threshold: Option<usize>,
 }
 
 impl ClientboundEncoder {
     pub fn new() -> ClientboundEncoder {
-        ClientboundEncoder { encrypter: None }
+        ClientboundEncoder {
+            encrypter: None,
+            compression_threshold: None,
+        }
     }
 
     pub fn enable_encryption(&mut self, key: &[u8]) -> anyhow::Result<()> {
         info!("encoder enabling encryption");
 
-        self.encrypter = Some(Crypter::new(
-            Cipher::aes_128_cfb8(),
-            Mode::Encrypt,
-            key,
-            Some(key), // Both sides use the shared secret as the Key and IV
-        )?);
+        // Both sides use the shared secret as the Key and IV
+        self.encrypter = Some(Cfb8::new(key, key)?);
         Ok(())
     }
+
+    /// Switches the encoder over to the compressed packet format. Packets whose
+    /// uncompressed size is at least `threshold` bytes will be zlib compressed.
+    pub fn enable_compression(&mut self, threshold: usize) {
+        info!("encoder enabling compression (threshold {})", threshold);
+
+        self.compression_threshold = Some(threshold);
+    }
+}
+
+/// Appends the (unencrypted) frame for a packet to the destination buffer.
+fn write_frame(
+    packet_id: VarInt,
+    data: BytesMut,
+    compression_threshold: Option<usize>,
+    dst: &mut BytesMut,
+) -> Result<(), Error> {
+    let uncompressed_length = packet_id.size() + data.len();
+
+    match compression_threshold {
+        None => {
+            let buffer_length = VarInt::new(
+                uncompressed_length
+                    .try_into()
+                    .context("Packet length exceeds size of 32 bit integer")?,
+            );
+
+            dst.reserve(buffer_length.size() + uncompressed_length);
+            buffer_length.write_to(dst);
+            packet_id.write_to(dst);
+            dst.extend_from_slice(data.as_ref());
+        }
+        Some(threshold) if uncompressed_length < threshold => {
+            // Packets under the threshold are sent with a data length of
+            // zero to mark them as uncompressed.
+            let data_length = VarInt::new(0);
+            let buffer_length = VarInt::new(
+                (data_length.size() + uncompressed_length)
+                    .try_into()
+                    .context("Packet length exceeds size of 32 bit integer")?,
+            );
+
+            dst.reserve(buffer_length.size() + buffer_length.value() as usize);
+            buffer_length.write_to(dst);
+            data_length.write_to(dst);
+            packet_id.write_to(dst);
+            dst.extend_from_slice(data.as_ref());
+        }
+        Some(_) => {
+            let data_length = VarInt::new(
+                uncompressed_length
+                    .try_into()
+                    .context("Packet length exceeds size of 32 bit integer")?,
+            );
+
+            let mut packet_id_bytes = BytesMut::with_capacity(packet_id.size());
+            packet_id.write_to(&mut packet_id_bytes);
+
+            let mut compressor = ZlibEncoder::new(
+                Vec::with_capacity(uncompressed_length),
+                Compression::default(),
+            );
+            compressor.write_all(&packet_id_bytes)?;
+            compressor.write_all(&data)?;
+            let compressed = compressor.finish()?;
+
+            let buffer_length = VarInt::new(
+                (data_length.size() + compressed.len())
+                    .try_into()
+                    .context("Packet length exceeds size of 32 bit integer")?,
+            );
+
+            dst.reserve(buffer_length.size() + buffer_length.value() as usize);
+            buffer_length.write_to(dst);
+            data_length.write_to(dst);
+            dst.put_slice(&compressed);
+        }
+    }
+
+    Ok(())
 }
 
 impl Encoder<ClientboundPacket> for ClientboundEncoder {
     type Error = Error;
 
     fn encode(&mut self, item: ClientboundPacket, dst: &mut BytesMut) -> Result<(), Error> {
-        // TODO reduce the number of allocations here...
         let packet_id = VarInt::new(item.packet_id());
         let data = item.data();
 
-        let buffer_length = (packet_id.size() + data.len())
-            .try_into()
-            .context("Packet length exceeds size of 32 bit integer")?;
-        let buffer_length = VarInt::new(buffer_length);
+        // Compression happens before encryption, so the whole frame is built
+        // up before it gets encrypted in place.
+        let start = dst.len();
+        write_frame(packet_id, data, self.compression_threshold, dst)?;
 
-        let length = buffer_length.size() + buffer_length.value() as usize;
-        dst.reserve(length + 16); // 16 is cipher block size
-
-        // TODO
         if let Some(encrypter) = self.encrypter.as_mut() {
-            let mut temp = BytesMut::with_capacity(length);
-            buffer_length.write_to(&mut temp);
-            packet_id.write_to(&mut temp);
-            temp.extend_from_slice(data.as_ref());
-
-            dst.resize(length, 0);
-            encrypter.update(&temp, dst)?;
-        } else {
-            buffer_length.write_to(dst);
-            packet_id.write_to(dst);
-            dst.extend_from_slice(data.as_ref());
+            encrypter.encrypt(&mut dst[start..]);
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn codecs(
+        encryption: bool,
+        compression_threshold: Option<usize>,
+    ) -> (ClientboundEncoder, ServerboundDecoder) {
+        let key = [7u8; 16];
+        let mut encoder = ClientboundEncoder::new();
+        let mut decoder = ServerboundDecoder::new();
+
+        if encryption {
+            encoder.enable_encryption(&key).unwrap();
+            decoder.enable_encryption(&key).unwrap();
+        }
+
+        if let Some(threshold) = compression_threshold {
+            encoder.enable_compression(threshold);
+            decoder.enable_compression(threshold);
+        }
+
+        (encoder, decoder)
+    }
+
+    /// Feeds the wire data to the decoder in pieces, cut at the split points,
+    /// decoding as many packets as possible after each one like `FramedRead`.
+    fn decode_in_pieces(
+        decoder: &mut ServerboundDecoder,
+        wire: &[u8],
+        split_points: &[usize],
+    ) -> Vec<ServerboundPacket> {
+        let mut split_points = split_points.to_vec();
+        split_points.push(wire.len());
+        split_points.sort_unstable();
+
+        let mut src = BytesMut::new();
+        let mut packets = Vec::new();
+        let mut start = 0;
+
+        for end in split_points {
+            src.extend_from_slice(&wire[start..end]);
+            start = end;
+
+            while let Some(packet) = decoder.decode(&mut src).unwrap() {
+                packets.push(packet);
+            }
+        }
+
+        assert!(src.is_empty());
+        packets
+    }
+
+    fn round_trip(
+        encoder: &mut ClientboundEncoder,
+        decoder: &mut ServerboundDecoder,
+        packet_id: i32,
+        data: &[u8],
+    ) -> ServerboundPacket {
+        let mut wire = BytesMut::new();
+        encoder
+            .encode(
+                ClientboundPacket::new(packet_id, BytesMut::from(data)),
+                &mut wire,
+            )
+            .unwrap();
+
+        let packet = decoder.decode(&mut wire).unwrap().unwrap();
+        assert!(wire.is_empty());
+
+        packet
+    }
+
+    #[test]
+    fn compressed_packet_round_trip() {
+        let mut encoder = ClientboundEncoder::new();
+        let mut decoder = ServerboundDecoder::new();
+        encoder.enable_compression(64);
+        decoder.enable_compression(64);
+
+        let data = vec![0x42; 1000];
+        let packet = round_trip(&mut encoder, &mut decoder, 0x22, &data);
+
+        assert_eq!(packet.packet_id(), 0x22);
+        assert_eq!(packet.data().as_ref(), &data[..]);
+    }
+
+    #[test]
+    fn small_packet_is_not_compressed() {
+        let mut encoder = ClientboundEncoder::new();
+        encoder.enable_compression(64);
+
+        let mut wire = BytesMut::new();
+        encoder
+            .encode(
+                ClientboundPacket::new(0x01, BytesMut::from(&[1, 2, 3][..])),
+                &mut wire,
+            )
+            .unwrap();
+
+        // Frame length, a zero data length, then the raw packet ID and data.
+        assert_eq!(wire.as_ref(), &[0x05, 0x00, 0x01, 1, 2, 3]);
+    }
+
+    #[test]
+    fn compressed_and_encrypted_round_trip() {
+        let key = [7u8; 16];
+        let mut encoder = ClientboundEncoder::new();
+        let mut decoder = ServerboundDecoder::new();
+        encoder.enable_encryption(&key).unwrap();
+        decoder.enable_encryption(&key).unwrap();
+        encoder.enable_compression(16);
+        decoder.enable_compression(16);
+
+        for size in &[0, 15, 16, 300] {
+            let data: Vec<u8> = (0..*size).map(|i| i as u8).collect();
+            let packet = round_trip(&mut encoder, &mut decoder, 0x10, &data);
+
+            assert_eq!(packet.packet_id(), 0x10);
+            assert_eq!(packet.data().as_ref(), &data[..]);
+        }
+    }
+
+    #[test]
+    fn compressed_packet_below_threshold_is_rejected() {
+        let mut encoder = ClientboundEncoder::new();
+        let mut decoder = ServerboundDecoder::new();
+        encoder.enable_compression(0);
+        decoder.enable_compression(256);
+
+        let mut wire = BytesMut::new();
+        encoder
+            .encode(
+                ClientboundPacket::new(0x01, BytesMut::from(&[1, 2, 3][..])),
+                &mut wire,
+            )
+            .unwrap();
+
+        assert!(decoder.decode(&mut wire).is_err());
+    }
+
+    #[test]
+    fn uncompressed_packet_above_threshold_is_accepted() {
+        let mut encoder = ClientboundEncoder::new();
+        let mut decoder = ServerboundDecoder::new();
+        // Sent by a peer that never compresses, as vanilla allows
+        encoder.enable_compression(usize::MAX);
+        decoder.enable_compression(64);
+
+        let data = vec![0x42; 1000];
+        let packet = round_trip(&mut encoder, &mut decoder, 0x22, &data);
+
+        assert_eq!(packet.packet_id(), 0x22);
+        assert_eq!(packet.data().as_ref(), &data[..]);
+    }
+
+    #[test]
+    fn encrypted_frames_arrive_a_byte_at_a_time() {
+        let (mut encoder, mut decoder) = codecs(true, Some(16));
+
+        // One packet under the compression threshold and one over it
+        let sent = vec![vec![1, 2, 3], (0..200).map(|i| i as u8).collect()];
+
+        let mut wire = BytesMut::new();
+        for data in &sent {
+            encoder
+                .encode(
+                    ClientboundPacket::new(0x10, BytesMut::from(&data[..])),
+                    &mut wire,
+                )
+                .unwrap();
+        }
+
+        let split_points: Vec<usize> = (1..wire.len()).collect();
+        let packets = decode_in_pieces(&mut decoder, &wire, &split_points);
+
+        let received: Vec<Vec<u8>> = packets.into_iter().map(|p| p.data().to_vec()).collect();
+        assert_eq!(received, sent);
+    }
+
+    #[test]
+    fn encoding_appends_to_earlier_packets() {
+        let (mut encoder, _) = codecs(false, None);
+        let (mut encrypted_encoder, mut decoder) = codecs(true, None);
+
+        let mut plain = BytesMut::new();
+        let mut encrypted = BytesMut::new();
+        for id in 0..3 {
+            let packet = || ClientboundPacket::new(id, BytesMut::from(&[id as u8; 4][..]));
+            encoder.encode(packet(), &mut plain).unwrap();
+            encrypted_encoder.encode(packet(), &mut encrypted).unwrap();
+        }
+
+        assert_eq!(plain.len(), 3 * 6);
+        assert_eq!(&plain[..6], &[0x05, 0x00, 0, 0, 0, 0]);
+
+        // Only the new frame is encrypted each time, so the stream decrypts
+        // in one go.
+        decoder.decrypter.as_mut().unwrap().decrypt(&mut encrypted);
+        assert_eq!(encrypted, plain);
+    }
+
+    #[test]
+    fn decrypts_packets_buffered_with_the_encryption_response() {
+        let (mut plain_encoder, mut decoder) = codecs(false, None);
+        let (mut encrypted_encoder, _) = codecs(true, None);
+
+        let mut wire = BytesMut::new();
+        plain_encoder
+            .encode(
+                ClientboundPacket::new(0x01, BytesMut::from(&[1, 2][..])),
+                &mut wire,
+            )
+            .unwrap();
+        encrypted_encoder
+            .encode(
+                ClientboundPacket::new(0x02, BytesMut::from(&[3, 4][..])),
+                &mut wire,
+            )
+            .unwrap();
+
+        let response = decoder.decode(&mut wire).unwrap().unwrap();
+        assert_eq!(response.packet_id(), 0x01);
+
+        decoder.enable_encryption(&[7u8; 16]).unwrap();
+
+        let packet = decoder.decode(&mut wire).unwrap().unwrap();
+        assert_eq!(packet.packet_id(), 0x02);
+        assert_eq!(packet.data().as_ref(), &[3, 4]);
+        assert!(wire.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn packets_survive_any_split(
+            packets in prop::collection::vec(
+                (0..0x100i32, prop::collection::vec(any::<u8>(), 0..600)),
+                1..8,
+            ),
+            split_points in prop::collection::vec(any::<prop::sample::Index>(), 0..16),
+            encryption in any::<bool>(),
+            compression_threshold in prop::option::of(0..300usize),
+        ) {
+            let (mut encoder, mut decoder) = codecs(encryption, compression_threshold);
+
+            let mut wire = BytesMut::new();
+            for (packet_id, data) in &packets {
+                encoder
+                    .encode(ClientboundPacket::new(*packet_id, BytesMut::from(&data[..])), &mut wire)
+                    .unwrap();
+            }
+
+            let split_points: Vec<usize> = split_points
+                .iter()
+                .map(|index| index.index(wire.len() + 1))
+                .collect();
+            let decoded = decode_in_pieces(&mut decoder, &wire, &split_points);
+
+            prop_assert_eq!(decoded.len(), packets.len());
+            for (packet, (packet_id, data)) in decoded.into_iter().zip(&packets) {
+                prop_assert_eq!(packet.packet_id(), *packet_id);
+                prop_assert_eq!(&packet.data()[..], &data[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected_before_they_arrive() {
+        let mut decoder = ServerboundDecoder::new();
+        decoder.set_max_packet_size(1024);
+
+        // Just the length of a 1025 byte frame
+        let mut src = BytesMut::from(&[0x81, 0x08][..]);
+        let err = decoder.decode(&mut src).err().unwrap();
+
+        assert_eq!(
+            err.downcast_ref::<FrameError>(),
+            Some(&FrameError::TooLarge {
+                length: 1025,
+                max: 1024
+            })
+        );
+    }
+
+    #[test]
+    fn frames_up_to_the_limit_are_accepted() {
+        let (mut encoder, mut decoder) = codecs(false, None);
+        decoder.set_max_packet_size(100);
+
+        let data = vec![0; 99];
+        let packet = round_trip(&mut encoder, &mut decoder, 0x01, &data);
+
+        assert_eq!(packet.data().len(), 99);
+    }
+
+    #[test]
+    fn compressed_packets_are_limited_before_they_are_inflated() {
+        let (mut encoder, mut decoder) = codecs(false, Some(64));
+        decoder.set_max_packet_size(1024);
+
+        // Compresses down to a frame well under the limit
+        let mut wire = BytesMut::new();
+        encoder
+            .encode(
+                ClientboundPacket::new(0x01, BytesMut::from(&[0; 1024][..])),
+                &mut wire,
+            )
+            .unwrap();
+        assert!(wire.len() < 1024);
+
+        let err = decoder.decode(&mut wire).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<FrameError>(),
+            Some(&FrameError::TooLarge {
+                length: 1025,
+                max: 1024
+            })
+        );
+    }
+
+    #[test]
+    fn lengths_wider_than_21_bits_are_rejected() {
+        // The length isn't finished after 3 bytes
+        let mut src = BytesMut::from(&[0x80, 0x80, 0x80][..]);
+        let err = ServerboundDecoder::new().decode(&mut src).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<FrameError>(),
+            Some(&FrameError::LengthTooWide)
+        );
+
+        // A negative length
+        let mut src = BytesMut::from(&[0xff, 0xff, 0xff, 0xff, 0x0f][..]);
+        let err = ServerboundDecoder::new().decode(&mut src).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<FrameError>(),
+            Some(&FrameError::LengthTooWide)
+        );
+
+        // Two bytes could still be the start of a valid length
+        let mut src = BytesMut::from(&[0x80, 0x80][..]);
+        assert!(ServerboundDecoder::new()
+            .decode(&mut src)
+            .unwrap()
+            .is_none());
+    }
+}
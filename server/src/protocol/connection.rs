@@ -1,20 +1,88 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
+use bytes::BytesMut;
 use futures::{SinkExt, StreamExt};
-use log::debug;
-use openssl::pkey::Private;
-use openssl::rsa;
+use log::{debug, info, warn};
+use tokio::io::AsyncWriteExt;
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::time::{self, Instant, Interval};
 use tokio_util::codec::{FramedRead, FramedWrite};
 
-use crate::api;
-use crate::protocol::codec::{ClientboundEncoder, ServerboundDecoder};
-use crate::protocol::data_types::{Identifier, VarInt};
+use crate::api::{self, Authenticator, GameProfile, UnverifiedUsername};
+use crate::limits::{ConnectionLimits, Rejection};
+use crate::protocol::codec::{
+    ClientboundEncoder, FrameError, ServerboundDecoder, MAX_UNCOMPRESSED_PACKET_SIZE,
+};
+use crate::protocol::data_types::{Chat, Identifier, VarInt, MAX_STRING_LENGTH};
+use crate::protocol::forwarding::{
+    Forwarded, ForwardingMode, VELOCITY_CHANNEL, VELOCITY_FORWARDING_VERSION,
+};
+use crate::protocol::keep_alive::{KeepAlive, Tick, KEEP_ALIVE_INTERVAL};
+use crate::protocol::key::ServerKey;
+use crate::protocol::legacy;
 use crate::protocol::packets::{
-    handshake, login, play, status, ClientboundPacket, IntoPacket, ServerboundPacket,
+    handshake, login, play, status, ClientboundPacket, IntoPacket, PacketId, ServerboundPacket,
 };
+use crate::protocol::version::ProtocolVersion;
+use crate::registry;
+use crate::state::{OnlinePlayer, ServerState};
+use crate::virtual_host::VirtualHosts;
+
+/// How players are authenticated when they log in.
+#[derive(Copy, Clone)]
+pub enum AuthMode {
+    /// Players are authenticated against Mojang's session server. The
+    /// connection is always encrypted.
+    Online,
+    /// Players are trusted to be who they say they are and are given an
+    /// offline UUID. Encryption can still be negotiated, but since nobody is
+    /// checking the shared secret it only protects against eavesdropping.
+    Offline { encryption: bool },
+}
+
+/// What to do with a play packet whose ID the server doesn't know. Unknown
+/// packets in any other state always disconnect the client.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnknownPacketPolicy {
+    /// Log the packet and carry on.
+    Skip,
+    /// Disconnect the client, like vanilla does.
+    Reject,
+}
+
+impl UnknownPacketPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            UnknownPacketPolicy::Skip => "skip",
+            UnknownPacketPolicy::Reject => "reject",
+        }
+    }
+}
+
+impl fmt::Display for UnknownPacketPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for UnknownPacketPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<UnknownPacketPolicy, ()> {
+        match s {
+            "skip" => Ok(UnknownPacketPolicy::Skip),
+            "reject" => Ok(UnknownPacketPolicy::Reject),
+            _ => Err(()),
+        }
+    }
+}
 
 enum State {
     Handshaking,
@@ -24,6 +92,28 @@ enum State {
     Play,
 }
 
+impl State {
+    /// The longest packet a client has any reason to send in this state.
+    /// Anything longer is rejected before it's buffered, or decompressed.
+    fn max_packet_size(&self, forwarding_mode: ForwardingMode) -> usize {
+        match (self, forwarding_mode) {
+            // BungeeCord adds the player's details to the server address
+            (State::Handshaking, ForwardingMode::BungeeCord) => {
+                1 + 5 + (3 + MAX_STRING_LENGTH) + 2 + 1
+            }
+            // A handshake with the longest allowed server address
+            (State::Handshaking, _) => 1 + 5 + (2 + 255 * 4) + 2 + 1,
+            // A ping, with its long
+            (State::Status, _) => 1 + 8,
+            // Velocity answers a plugin request with the player's details
+            (State::Login, ForwardingMode::Velocity) => 1 + 5 + 1 + login::MAX_PLUGIN_RESPONSE_SIZE,
+            // An encryption response with the longest allowed arrays
+            (State::Login, _) | (State::Encrypt, _) => 1 + 2 * (2 + 512),
+            (State::Play, _) => MAX_UNCOMPRESSED_PACKET_SIZE,
+        }
+    }
+}
+
 // pub enum ConnectionContext {
 //     Handshaking(..),
 //     Status(..),
@@ -33,9 +123,42 @@ enum State {
 // }
 
 pub struct ConnectionHandler {
-    rsa_key: Arc<rsa::Rsa<Private>>,
+    hosts: Arc<VirtualHosts>,
+    /// The virtual host the client connected to, the default one until the
+    /// handshake says otherwise.
+    server: Arc<ServerState>,
+    server_key: Arc<ServerKey>,
+    authenticator: Arc<dyn Authenticator>,
+    limits: Arc<ConnectionLimits>,
+    /// The address of the client, if it could be determined.
+    peer_address: Option<SocketAddr>,
+    /// The protocol version the client sent in its handshake, or the latest
+    /// supported version if it sent one the server doesn't speak.
+    protocol_version: ProtocolVersion,
+    /// Set once the client has been disconnected, after which no more packets
+    /// are handled.
+    closed: bool,
+    /// Receives a message when the server is stopping.
+    shutdown: broadcast::Receiver<()>,
+    /// When the client is kicked if it hasn't logged in yet.
+    login_deadline: Option<Instant>,
+    keep_alive: KeepAlive,
+    /// Fires when the next keep alive is due, once the player is in the play
+    /// state.
+    keep_alive_timer: Option<Interval>,
     // Login Information
     username: Option<String>,
+    /// The player's details as forwarded by a proxy, until they're used to log
+    /// in.
+    forwarded: Option<Forwarded>,
+    /// The channels of login plugin requests the client hasn't answered yet,
+    /// by message ID.
+    plugin_requests: HashMap<i32, &'static str>,
+    next_message_id: i32,
+    /// The player's profile, available once they have been authenticated.
+    profile: Option<GameProfile>,
+    /// Keeps the player in the online player list while they're connected.
+    online: Option<OnlinePlayer>,
     verify_token: Option<[u8; 4]>,
 
     current_state: State,
@@ -51,28 +174,108 @@ pub struct ConnectionHandler {
 // }
 
 impl ConnectionHandler {
-    pub fn new(rsa_key: Arc<rsa::Rsa<Private>>, socket: TcpStream) -> ConnectionHandler {
+    /// Handles a connection from `peer_address`, which for connections through
+    /// a load balancer is the client's address rather than the socket's.
+    pub fn new(
+        hosts: Arc<VirtualHosts>,
+        server_key: Arc<ServerKey>,
+        authenticator: Arc<dyn Authenticator>,
+        limits: Arc<ConnectionLimits>,
+        socket: TcpStream,
+        peer_address: Option<SocketAddr>,
+    ) -> ConnectionHandler {
         let (socket_read, socket_write) = socket.into_split();
 
+        let server = hosts.default_host().clone();
+        // Stays subscribed to the default host even once the client has picked
+        // another, since that's the one the whole server is shut down through.
+        let shutdown = server.subscribe_shutdown();
+        let login_deadline = Some(Instant::now() + server.config().login_timeout);
+
+        let mut decoder = ServerboundDecoder::new();
+        decoder.set_max_packet_size(
+            State::Handshaking.max_packet_size(server.config().forwarding_mode),
+        );
+
         ConnectionHandler {
-            rsa_key,
+            hosts,
+            server,
+            server_key,
+            authenticator,
+            limits,
+            peer_address,
+            protocol_version: ProtocolVersion::LATEST,
+            closed: false,
+            shutdown,
+            login_deadline,
+            keep_alive: KeepAlive::new(),
+            keep_alive_timer: None,
             username: None,
+            forwarded: None,
+            plugin_requests: HashMap::new(),
+            next_message_id: 0,
+            profile: None,
+            online: None,
             verify_token: None,
 
             current_state: State::Handshaking,
-            reader: FramedRead::new(socket_read, ServerboundDecoder::new()),
+            reader: FramedRead::new(socket_read, decoder),
             writer: FramedWrite::new(socket_write, ClientboundEncoder::new()),
         }
     }
 
     pub async fn execute(mut self) -> Result<()> {
-        // The framed reader will close the stream when the connection is
-        // closed.
-        while let Some(msg) = self.reader.next().await {
-            match msg {
-                Ok(packet) => self.handle_packet(packet).await?,
-                Err(err) => return Err(err),
+        // Waits for the client's first byte, so clients that never send
+        // anything have to be timed out here too.
+        let starts_with_legacy_ping = tokio::select! {
+            result = legacy::starts_with_legacy_ping(self.reader.get_mut()) => result?,
+            _ = deadline(self.login_deadline) => return self.time_out().await,
+        };
+        if starts_with_legacy_ping {
+            return self.handle_legacy_ping().await;
+        }
+
+        while !self.closed {
+            let result = tokio::select! {
+                // The framed reader will close the stream when the connection
+                // is closed.
+                msg = self.reader.next() => match msg {
+                    Some(Ok(packet)) => self.handle_packet(packet).await,
+                    Some(Err(err)) => Err(err),
+                    None => break,
+                },
+                _ = next_keep_alive(&mut self.keep_alive_timer) => self.send_keep_alive().await,
+                _ = deadline(self.login_deadline) => {
+                    self.time_out().await?;
+                    break;
+                }
+                _ = self.shutdown.recv() => {
+                    let reason = Chat::translate("multiplayer.disconnect.server_shutdown", &[]);
+                    self.disconnect(reason).await?;
+                    break;
+                }
+            };
+
+            if let Err(err) = result {
+                // Let the client know what went wrong, the same way vanilla
+                // does.
+                let reason = Chat::translate(
+                    "disconnect.genericReason",
+                    &[&format!("Internal Exception: {}", err)],
+                );
+                if let Err(disconnect_err) = self.disconnect(reason).await {
+                    debug!("Could not send disconnect: {}", disconnect_err);
+                }
+
+                // A bad frame is the client's fault, kicking them is all
+                // there is to do.
+                if err.downcast_ref::<FrameError>().is_some() {
+                    return Ok(());
+                }
+
+                return Err(err);
             }
+
             self.writer.flush().await?;
         }
 
@@ -82,56 +285,283 @@ impl ConnectionHandler {
     }
 
     async fn send(&mut self, packet: ClientboundPacket) -> Result<()> {
+        // Play packets are defined with their native IDs
+        let packet = match self.current_state {
+            State::Play => ClientboundPacket::new(
+                self.protocol_version
+                    .clientbound_play_id(packet.packet_id()),
+                packet.data(),
+            ),
+            _ => packet,
+        };
+
         self.writer.send(packet).await?;
 
         Ok(())
     }
 
+    fn set_state(&mut self, state: State) {
+        let max_packet_size = state.max_packet_size(self.forwarding_mode());
+        self.reader
+            .decoder_mut()
+            .set_max_packet_size(max_packet_size);
+        self.current_state = state;
+    }
+
+    /// Kicks the client with the given reason, closing the connection. The
+    /// client can only be shown a reason during login and play.
+    async fn disconnect(&mut self, reason: Chat) -> Result<()> {
+        info!(
+            "Disconnecting {}: {}",
+            self.peer_address
+                .map_or("unknown address".to_string(), |address| address.to_string()),
+            reason.as_str()
+        );
+
+        self.closed = true;
+
+        match self.current_state {
+            State::Handshaking | State::Status => Ok(()),
+            State::Login | State::Encrypt => {
+                self.send(login::Disconnect::new(reason).into_packet())
+                    .await
+            }
+            State::Play => self.send(play::Disconnect::new(reason).into_packet()).await,
+        }
+    }
+
+    /// Kicks a client that took too long to log in. Only clients that got as
+    /// far as asking to log in count as timed out, not slow status pings.
+    async fn time_out(&mut self) -> Result<()> {
+        if matches!(self.current_state, State::Login | State::Encrypt) {
+            self.limits.reject(Rejection::TimedOut);
+        }
+
+        let reason = Chat::translate("multiplayer.disconnect.slow_login", &[]);
+        self.disconnect(reason).await
+    }
+
     async fn handle_packet(&mut self, packet: ServerboundPacket) -> Result<()> {
+        let id = packet.packet_id();
+
         match self.current_state {
-            State::Handshaking => match packet.packet_id() {
-                0x00 => self.handle_handshake(packet.parse()?).await?,
-                id => return Err(anyhow!("Unrecognized handshake packet id {}", id)),
+            State::Handshaking if self.forwarding_mode() == ForwardingMode::BungeeCord => {
+                match handshake::ForwardedServerbound::parse(packet)? {
+                    Some(handshake::ForwardedServerbound::Handshake(handshake)) => {
+                        self.handle_handshake(handshake.into()).await
+                    }
+                    None => Err(anyhow!("Unrecognized handshake packet id {:#04x}", id)),
+                }
+            }
+            State::Handshaking => match handshake::Serverbound::parse(packet)? {
+                Some(handshake::Serverbound::Handshake(handshake)) => {
+                    self.handle_handshake(handshake).await
+                }
+                None => Err(anyhow!("Unrecognized handshake packet id {:#04x}", id)),
             },
-            State::Status => match packet.packet_id() {
-                0x00 => self.handle_status_request(packet.parse()?).await?,
-                0x01 => self.handle_status_ping(packet.parse()?).await?,
-                id => return Err(anyhow!("Unrecognized status packet id {}", id)),
+            State::Status => match status::Serverbound::parse(packet)? {
+                Some(status::Serverbound::Request(request)) => {
+                    self.handle_status_request(request).await
+                }
+                Some(status::Serverbound::Ping(ping)) => self.handle_status_ping(ping).await,
+                None => Err(anyhow!("Unrecognized status packet id {:#04x}", id)),
             },
-            State::Login => match packet.packet_id() {
-                0x00 => self.handle_login_start(packet.parse()?).await?,
-                id => return Err(anyhow!("Unrecognized login packet id {}", id)),
+            State::Login => match login::Serverbound::parse(packet)? {
+                Some(login::Serverbound::Start(start)) => self.handle_login_start(start).await,
+                Some(login::Serverbound::PluginResponse(response)) => {
+                    self.handle_login_plugin_response(response).await
+                }
+                _ => Err(anyhow!("Unexpected login packet id {:#04x}", id)),
             },
-            State::Encrypt => match packet.packet_id() {
-                0x01 => {
-                    self.handle_login_encryption_response(packet.parse()?)
-                        .await?
+            State::Encrypt => match login::Serverbound::parse(packet)? {
+                Some(login::Serverbound::EncryptionResponse(response)) => {
+                    self.handle_login_encryption_response(response).await
                 }
-                id => return Err(anyhow!("Unrecognized login packet id {}", id)),
+                _ => Err(anyhow!("Unexpected login packet id {:#04x}", id)),
             },
-            State::Play => match packet.packet_id() {
-                _ => unimplemented!(),
+            State::Play => self.handle_play_packet(packet).await,
+        }
+    }
+
+    async fn handle_play_packet(&mut self, packet: ServerboundPacket) -> Result<()> {
+        let id = packet.packet_id();
+
+        let native_id = match self.protocol_version.serverbound_play_id(id) {
+            Some(native_id) => native_id,
+            None => {
+                debug!(
+                    "ignoring {} play packet {:#04x} with no {} equivalent",
+                    self.protocol_version,
+                    id,
+                    ProtocolVersion::NATIVE
+                );
+                return Ok(());
+            }
+        };
+
+        // Only packets with a handler are parsed, so a field the client fills
+        // in differently than expected can't get a player kicked for sending
+        // something the server would have ignored anyway.
+        match native_id {
+            play::KeepAliveResponse::PACKET_ID => {
+                let packet = ServerboundPacket::new(native_id, packet.data());
+                self.handle_keep_alive(packet.parse()?).await
+            }
+            // TODO handle the rest of the play packets
+            id if play::Serverbound::IDS.contains(&id) => {
+                debug!("ignoring play packet {:#04x}", id);
+                Ok(())
+            }
+            _ => match self.server.config().unknown_packet_policy {
+                UnknownPacketPolicy::Skip => {
+                    debug!("skipping unrecognized play packet id {:#04x}", id);
+                    Ok(())
+                }
+                UnknownPacketPolicy::Reject => {
+                    Err(anyhow!("Unrecognized play packet id {:#04x}", id))
+                }
             },
         }
+    }
+
+    async fn send_keep_alive(&mut self) -> Result<()> {
+        match self.keep_alive.tick(Instant::now().into_std()) {
+            Tick::Send(id) => self.send(play::KeepAlive::new(id).into_packet()).await,
+            Tick::TimedOut => {
+                self.disconnect(Chat::translate("disconnect.timeout", &[]))
+                    .await
+            }
+        }
+    }
+
+    async fn handle_keep_alive(&mut self, response: play::KeepAliveResponse) -> Result<()> {
+        if !self
+            .keep_alive
+            .received(response.id, Instant::now().into_std())
+        {
+            // Vanilla treats this as the client having timed out
+            debug!("unexpected keep alive {}", response.id);
+            return self
+                .disconnect(Chat::translate("disconnect.timeout", &[]))
+                .await;
+        }
+
+        let latency = self.keep_alive.latency();
+        debug!("keep alive answered, latency now {}ms", latency.as_millis());
+
+        if let Some(online) = &self.online {
+            online.set_latency(latency);
+        }
+
+        Ok(())
+    }
+
+    /// Answers a server list ping from a pre-Netty client, after which the
+    /// connection is closed.
+    async fn handle_legacy_ping(&mut self) -> Result<()> {
+        let ping = legacy::read_legacy_ping(self.reader.get_mut()).await?;
+
+        debug!("handling legacy ping {:?}", ping);
+
+        // Only 1.6 clients say which address they connected to
+        if let legacy::LegacyPing::V1_6 { hostname, .. } = &ping {
+            self.server = self.hosts.route(hostname).clone();
+        }
+
+        let status = self.server.status(ProtocolVersion::LATEST);
+        let response = legacy::legacy_ping_response(&ping, &status);
+        self.writer.get_mut().write_all(&response).await?;
 
         Ok(())
     }
 
     async fn handle_handshake(&mut self, handshake: handshake::Handshake) -> Result<()> {
-        debug!("handling handshake packet");
+        debug!(
+            "handling handshake packet (protocol {}, address {}:{})",
+            handshake.protocol_version().value(),
+            handshake.server_address(),
+            handshake.server_port()
+        );
 
-        self.current_state = match handshake.next_state() {
-            handshake::NextState::Status => State::Status,
-            handshake::NextState::Login => State::Login,
-        };
+        self.server = self.hosts.route(&handshake.server_address()).clone();
+
+        let client_version = handshake.protocol_version().value();
+        let supported_version = ProtocolVersion::from_id(client_version);
+        self.protocol_version = supported_version.unwrap_or(ProtocolVersion::LATEST);
+
+        match handshake.next_state() {
+            handshake::NextState::Status => self.set_state(State::Status),
+            handshake::NextState::Login => {
+                self.set_state(State::Login);
+
+                // Same messages as vanilla
+                if supported_version.is_none() {
+                    let latest = ProtocolVersion::LATEST;
+                    let reason = if client_version < latest.id() {
+                        format!("Outdated client! Please use {}", latest.name())
+                    } else {
+                        format!("Outdated server! I'm still on {}", latest.name())
+                    };
+
+                    return self.disconnect(Chat::text(&reason)).await;
+                }
+
+                let address = self.peer_address.map(|address| address.ip());
+                if let Some(address) = address {
+                    if !self.limits.allow_login(address, std::time::Instant::now()) {
+                        // Same message as Spigot
+                        let reason = "Connection throttled! Please wait before reconnecting.";
+                        return self.disconnect(Chat::text(reason)).await;
+                    }
+                }
+
+                if self.forwarding_mode() == ForwardingMode::BungeeCord {
+                    return self
+                        .handle_bungeecord_forwarding(&handshake.server_address())
+                        .await;
+                }
+            }
+        }
 
         Ok(())
     }
 
+    /// Proxies sit in front of every virtual host, so the forwarding mode comes
+    /// from the default host rather than the one the client picked.
+    fn forwarding_mode(&self) -> ForwardingMode {
+        self.hosts.default_host().config().forwarding_mode
+    }
+
+    async fn handle_bungeecord_forwarding(&mut self, server_address: &str) -> Result<()> {
+        match Forwarded::from_bungeecord(server_address) {
+            Ok(forwarded) => {
+                debug!(
+                    "player {} forwarded from {}",
+                    forwarded.uuid, forwarded.address
+                );
+
+                // Keep the port, the proxy doesn't say which one the player
+                // used.
+                let port = self.peer_address.map_or(0, |address| address.port());
+                self.peer_address = Some(SocketAddr::new(forwarded.address, port));
+                self.forwarded = Some(forwarded);
+
+                Ok(())
+            }
+            Err(err) => {
+                debug!("no forwarded data: {:#}", err);
+
+                // Same message as Spigot
+                let reason = "If you wish to use IP forwarding, please enable it in your BungeeCord config as well!";
+                self.disconnect(Chat::text(reason)).await
+            }
+        }
+    }
+
     async fn handle_status_request(&mut self, _status: status::Request) -> Result<()> {
         debug!("handling status request packet");
 
-        let response = status::Response::new(1337, 69, "Hello World".to_string(), None);
+        let response = self.server.status(self.protocol_version);
         self.send(response.into_packet()).await?;
 
         Ok(())
@@ -149,20 +579,109 @@ impl ConnectionHandler {
     async fn handle_login_start(&mut self, start: login::Start) -> Result<()> {
         debug!("handling login start packet");
 
+        self.username = Some(start.username());
+
+        // The proxy has already authenticated the player
+        if let Some(forwarded) = self.forwarded.take() {
+            let profile = forwarded.into_profile(self.username.as_ref().unwrap());
+            return self.finish_login(profile).await;
+        }
+
+        if self.forwarding_mode() == ForwardingMode::Velocity {
+            let data = vec![VELOCITY_FORWARDING_VERSION];
+            return self.send_login_plugin_request(VELOCITY_CHANNEL, data).await;
+        }
+
+        if let AuthMode::Offline { encryption: false } = self.server.config().auth_mode() {
+            let profile = GameProfile::offline(self.username.as_ref().unwrap())?;
+            return self.finish_login(profile).await;
+        }
+
         let verify_token = rand::random();
-        let public_key = self.rsa_key.public_key_to_der()?;
+        let public_key = self.server_key.public_key_der().to_vec();
 
         let encryption_request = login::EncryptionRequest::new(public_key, verify_token);
 
-        self.username = Some(start.username());
         self.verify_token = Some(verify_token);
 
-        self.current_state = State::Encrypt;
+        self.set_state(State::Encrypt);
         self.send(encryption_request.into_packet()).await?;
 
         Ok(())
     }
 
+    /// Asks the client something on a login plugin channel. The answer is
+    /// passed to `handle_login_plugin_response`.
+    async fn send_login_plugin_request(
+        &mut self,
+        channel: &'static str,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let message_id = self.next_message_id;
+        self.next_message_id += 1;
+        self.plugin_requests.insert(message_id, channel);
+
+        let request = login::PluginRequest::new(message_id, channel, data);
+        self.send(request.into_packet()).await
+    }
+
+    async fn handle_login_plugin_response(
+        &mut self,
+        response: login::PluginResponse,
+    ) -> Result<()> {
+        debug!("handling login plugin response packet");
+
+        let (message_id, data) = response.into_parts();
+
+        let channel = match self.plugin_requests.remove(&message_id) {
+            Some(channel) => channel,
+            None => {
+                let reason =
+                    Chat::translate("multiplayer.disconnect.unexpected_query_response", &[]);
+                return self.disconnect(reason).await;
+            }
+        };
+
+        match channel {
+            VELOCITY_CHANNEL => self.handle_velocity_forwarding(data).await,
+            _ => Err(anyhow!("Response on unknown plugin channel {}", channel)),
+        }
+    }
+
+    async fn handle_velocity_forwarding(&mut self, data: Option<BytesMut>) -> Result<()> {
+        // Clients that didn't come through the proxy don't understand the
+        // request. Same messages as Paper.
+        let data = match data {
+            Some(data) => data,
+            None => {
+                let reason = "This server requires you to connect with Velocity.";
+                return self.disconnect(Chat::text(reason)).await;
+            }
+        };
+
+        let secret = self.hosts.default_host().config().forwarding_secret.clone();
+        match Forwarded::from_velocity(&data, secret.as_bytes()) {
+            Ok(forwarded) => {
+                debug!(
+                    "player {} forwarded from {}",
+                    forwarded.uuid, forwarded.address
+                );
+
+                let port = self.peer_address.map_or(0, |address| address.port());
+                self.peer_address = Some(SocketAddr::new(forwarded.address, port));
+
+                let profile = forwarded.into_profile(self.username.as_ref().unwrap());
+                self.finish_login(profile).await
+            }
+            Err(err) => {
+                warn!("Could not verify forwarded player: {:#}", err);
+
+                self.disconnect(Chat::text("Unable to verify player details"))
+                    .await
+            }
+        }
+    }
+
     async fn handle_login_encryption_response(
         &mut self,
         response: login::EncryptionResponse,
@@ -171,32 +690,20 @@ impl ConnectionHandler {
 
         let (encryped_shared_secret, encryped_verify_token) = response.into_parts();
 
-        let mut shared_secret_decrypted = [0u8; 128];
-        let num_bytes = self.rsa_key.private_decrypt(
-            &encryped_shared_secret,
-            &mut shared_secret_decrypted,
-            rsa::Padding::PKCS1,
-        )?;
+        let shared_secret_decrypted = self.server_key.decrypt(&encryped_shared_secret)?;
 
-        if num_bytes < 16 {
+        if shared_secret_decrypted.len() < 16 {
             return Err(anyhow!("Decryption of shared secret failed"));
         }
 
-        let mut verify_token_decrypted = [0u8; 128];
-        let num_bytes = self.rsa_key.private_decrypt(
-            &encryped_verify_token,
-            &mut verify_token_decrypted,
-            rsa::Padding::PKCS1,
-        )?;
+        let verify_token_decrypted = self.server_key.decrypt(&encryped_verify_token)?;
 
-        if num_bytes < 4 {
+        if verify_token_decrypted.len() < 4 {
             return Err(anyhow!("Decryption of verify token failed"));
         }
 
-        if self.verify_token.as_ref().unwrap() != &verify_token_decrypted[..4] {
-            return Err(anyhow!("Verify token does not match"));
-        }
-
+        // The client encrypts everything after its response, so any disconnect
+        // has to be encrypted too.
         self.reader
             .decoder_mut()
             .enable_encryption(&shared_secret_decrypted[..16])?;
@@ -205,53 +712,401 @@ impl ConnectionHandler {
             .encoder_mut()
             .enable_encryption(&shared_secret_decrypted[..16])?;
 
-        let uuid = api::authenticate(
-            self.username.as_ref().unwrap(),
-            &shared_secret_decrypted[..16],
-            &self.rsa_key.public_key_to_der()?,
-        )
-        .await?;
+        if self.verify_token.as_ref().unwrap() != &verify_token_decrypted[..4] {
+            return Err(anyhow!("Verify token does not match"));
+        }
+
+        let profile = match self.server.config().auth_mode() {
+            AuthMode::Online => {
+                let server_hash = api::server_hash(
+                    &shared_secret_decrypted[..16],
+                    self.server_key.public_key_der(),
+                );
+
+                let result = self
+                    .authenticator
+                    .authenticate(
+                        self.username.as_ref().unwrap(),
+                        &server_hash,
+                        self.peer_address.map(|address| address.ip()),
+                    )
+                    .await;
+
+                match result {
+                    Ok(profile) => profile,
+                    Err(err) => {
+                        warn!("Could not authenticate: {:#}", err);
+
+                        let reason = if err.is::<UnverifiedUsername>() {
+                            "multiplayer.disconnect.unverified_username"
+                        } else {
+                            "multiplayer.disconnect.authservers_down"
+                        };
+                        return self.disconnect(Chat::translate(reason, &[])).await;
+                    }
+                }
+            }
+            AuthMode::Offline { .. } => GameProfile::offline(self.username.as_ref().unwrap())?,
+        };
+
+        self.finish_login(profile).await
+    }
+
+    /// Completes the login sequence once the player's profile is known and
+    /// moves the connection into the play state.
+    async fn finish_login(&mut self, profile: GameProfile) -> Result<()> {
+        // Only checked now the player's UUID can be trusted
+        let address = self.peer_address.map(|address| address.ip());
+        if let Some(reason) = self.server.login_refusal(&profile, address) {
+            return self.disconnect(reason).await;
+        }
+
+        self.online = match self.server.add_player(&profile) {
+            Some(online) => Some(online),
+            None => {
+                let reason = Chat::translate("multiplayer.disconnect.server_full", &[]);
+                return self.disconnect(reason).await;
+            }
+        };
+
+        if let Some(threshold) = self.server.config().network_compression_threshold {
+            // Set Compression itself is sent uncompressed, every packet after
+            // it uses the compressed format.
+            let set_compression = login::SetCompression::new(VarInt::new(threshold as i32));
+            self.send(set_compression.into_packet()).await?;
+
+            self.writer.encoder_mut().enable_compression(threshold);
+            self.reader.decoder_mut().enable_compression(threshold);
+        }
 
-        let success = login::Success::new(&uuid, self.username.as_ref().unwrap());
+        // The session server's copy of the name has the correct capitalization.
+        let success = login::Success::new(&profile.uuid, &profile.name);
 
         self.send(success.into_packet()).await?;
 
-        self.current_state = State::Play;
-
-        // TODO move this elsewhere?
-        // A play handler?
-
-        /* entity_id: i32,
-        is_hardcore: bool,
-        gamemode: u8,
-        previous_gamemode: i8,
-        world_names: Vec<Identifier>,
-        dimension_codec: Identifier,
-        hashed_seed: i64,
-        max_players: u8,
-        level_type: String,
-        view_distance: VarInt,
-        reduced_debug_info: bool,
-        enable_respawn_screen: bool,
-        */
+        info!(
+            "{} ({}) logged in, {} players online",
+            profile.name,
+            profile.uuid,
+            self.server.online_count()
+        );
+        self.profile = Some(profile);
+
+        self.set_state(State::Play);
+        self.login_deadline = None;
+        // The first keep alive goes out after a full interval, like vanilla.
+        self.keep_alive_timer = Some(time::interval_at(
+            Instant::now() + KEEP_ALIVE_INTERVAL,
+            KEEP_ALIVE_INTERVAL,
+        ));
+
+        let server = self.server.clone();
+        let config = server.config();
+        let world = server.world();
+        let entity_id = self.online.as_ref().map_or(0, |online| online.entity_id());
 
         let join_game = play::JoinGame::new(
-            0,
-            false,
-            0,
-            0,
-            Vec::new(),
-            Identifier::new("whatever".to_string()),
-            0,
-            0,
-            "flat".to_string(),
-            VarInt::new(0),
+            entity_id,
+            config.hardcore,
+            config.gamemode.id(),
+            -1,
+            vec![Identifier::new(world.name.to_string())],
+            registry::dimension_codec()?,
+            registry::dimension_type(world.dimension_type)?.to_nbt()?,
+            Identifier::new(world.name.to_string()),
+            world.hashed_seed(),
+            VarInt::new(config.max_players.min(i32::MAX as usize) as i32),
+            VarInt::new(config.view_distance as i32),
             false,
+            true,
             false,
+            world.is_flat,
         );
-
         self.send(join_game.into_packet()).await?;
 
+        let [x, y, z] = world.spawn;
+        let position = play::PlayerPositionAndLook::new(x, y, z, 0.0, 0.0, 0, VarInt::new(0));
+        self.send(position.into_packet()).await?;
+
         Ok(())
     }
 }
+
+/// Waits until the deadline, or forever if there isn't one.
+async fn deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::delay_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Waits for the next keep alive to be due, or forever if keep alives aren't
+/// being sent yet.
+async fn next_keep_alive(timer: &mut Option<Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => futures::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::access::AccessLists;
+    use crate::api::MojangAuthenticator;
+    use crate::config::Config;
+    use crate::protocol::data_types::{DataType, SizedDataType};
+    use crate::protocol::forwarding;
+
+    /// A proxy's connection to a server with the given config. Framing is the
+    /// same both ways without compression or encryption, so the proxy writes
+    /// with the server's encoder and reads with its decoder.
+    struct Proxy {
+        reader: FramedRead<OwnedReadHalf, ServerboundDecoder>,
+        writer: FramedWrite<OwnedWriteHalf, ClientboundEncoder>,
+        limits: Arc<ConnectionLimits>,
+    }
+
+    impl Proxy {
+        async fn connect(config: Config) -> Proxy {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+
+            let server = Arc::new(ServerState::new(
+                Arc::new(config),
+                None,
+                AccessLists::default(),
+            ));
+            let hosts = Arc::new(VirtualHosts::load(Path::new("does-not-exist"), server).unwrap());
+            let server_key = Arc::new(ServerKey::generate(1024).unwrap());
+            // Never used, the proxy authenticates players
+            let authenticator = Arc::new(
+                MojangAuthenticator::new("http://127.0.0.1:1/", Duration::from_secs(1), false)
+                    .unwrap(),
+            );
+
+            let limits = Arc::new(ConnectionLimits::new(None, None));
+            let server_limits = limits.clone();
+
+            tokio::spawn(async move {
+                let (socket, _) = listener.accept().await.unwrap();
+                let peer_address = socket.peer_addr().ok();
+                let handler = ConnectionHandler::new(
+                    hosts,
+                    server_key,
+                    authenticator,
+                    server_limits,
+                    socket,
+                    peer_address,
+                );
+                let _ = handler.execute().await;
+            });
+
+            let (read, write) = TcpStream::connect(address).await.unwrap().into_split();
+            Proxy {
+                reader: FramedRead::new(read, ServerboundDecoder::new()),
+                writer: FramedWrite::new(write, ClientboundEncoder::new()),
+                limits,
+            }
+        }
+
+        async fn send(&mut self, packet_id: i32, data: BytesMut) {
+            self.writer
+                .send(ClientboundPacket::new(packet_id, data))
+                .await
+                .unwrap();
+        }
+
+        async fn receive(&mut self) -> ServerboundPacket {
+            self.reader.next().await.unwrap().unwrap()
+        }
+
+        async fn handshake(&mut self) {
+            self.handshake_to(2).await;
+        }
+
+        async fn handshake_to(&mut self, next_state: i32) {
+            let mut handshake = BytesMut::new();
+            VarInt::new(ProtocolVersion::LATEST.id()).write_to(&mut handshake);
+            "localhost".to_string().write_to(&mut handshake);
+            25565u16.write_to(&mut handshake);
+            VarInt::new(next_state).write_to(&mut handshake);
+            self.send(0x00, handshake).await;
+        }
+
+        /// Starts logging a player in, returning the server's plugin request.
+        async fn log_in(&mut self, username: &str) -> (i32, String, BytesMut) {
+            self.handshake().await;
+
+            let mut start = BytesMut::new();
+            username.to_string().write_to(&mut start);
+            self.send(0x00, start).await;
+
+            let request = self.receive().await;
+            assert_eq!(request.packet_id(), 0x04);
+
+            let mut data = request.data();
+            let message_id = VarInt::read_from(&mut data).unwrap().value();
+            let channel = String::read_from_sized(&mut data, MAX_STRING_LENGTH).unwrap();
+            (message_id, channel, data)
+        }
+
+        async fn respond(&mut self, message_id: i32, data: Option<&[u8]>) {
+            let mut response = BytesMut::new();
+            VarInt::new(message_id).write_to(&mut response);
+            data.is_some().write_to(&mut response);
+            response.extend_from_slice(data.unwrap_or_default());
+            self.send(0x02, response).await;
+        }
+
+        async fn disconnect_reason(&mut self) -> String {
+            let disconnect = self.receive().await;
+            assert_eq!(disconnect.packet_id(), 0x00);
+
+            String::read_from_sized(&mut disconnect.data(), MAX_STRING_LENGTH).unwrap()
+        }
+    }
+
+    fn velocity_config(secret: &str) -> Config {
+        Config {
+            network_compression_threshold: None,
+            forwarding_mode: ForwardingMode::Velocity,
+            forwarding_secret: secret.to_string(),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_in_players_forwarded_by_velocity() {
+        let mut proxy = Proxy::connect(velocity_config("secret")).await;
+
+        let (message_id, channel, data) = proxy.log_in("notch").await;
+        assert_eq!(channel, VELOCITY_CHANNEL);
+        assert_eq!(&data[..], &[VELOCITY_FORWARDING_VERSION]);
+
+        let forwarded = forwarding::velocity_data(b"secret", 1);
+        proxy.respond(message_id, Some(&forwarded)).await;
+
+        let success = proxy.receive().await;
+        assert_eq!(success.packet_id(), 0x02);
+        let mut data = success.data();
+        // The forwarded profile wins over the name the player logged in with
+        assert_eq!(
+            Uuid::read_from(&mut data).unwrap(),
+            Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        );
+        assert_eq!(
+            String::read_from_sized(&mut data, MAX_STRING_LENGTH).unwrap(),
+            "Notch"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_players_velocity_did_not_sign() {
+        let mut proxy = Proxy::connect(velocity_config("another secret")).await;
+
+        let (message_id, _, _) = proxy.log_in("notch").await;
+        let forwarded = forwarding::velocity_data(b"secret", 1);
+        proxy.respond(message_id, Some(&forwarded)).await;
+
+        assert!(proxy
+            .disconnect_reason()
+            .await
+            .contains("Unable to verify player details"));
+    }
+
+    #[tokio::test]
+    async fn rejects_players_not_behind_velocity() {
+        let mut proxy = Proxy::connect(velocity_config("secret")).await;
+
+        // What a vanilla client says to any plugin request
+        let (message_id, _, _) = proxy.log_in("notch").await;
+        proxy.respond(message_id, None).await;
+
+        assert!(proxy
+            .disconnect_reason()
+            .await
+            .contains("requires you to connect with Velocity"));
+    }
+
+    #[tokio::test]
+    async fn rejects_responses_to_unknown_requests() {
+        let mut proxy = Proxy::connect(velocity_config("secret")).await;
+
+        let (message_id, _, _) = proxy.log_in("notch").await;
+        proxy.respond(message_id + 1, None).await;
+
+        assert!(proxy
+            .disconnect_reason()
+            .await
+            .contains("multiplayer.disconnect.unexpected_query_response"));
+    }
+
+    #[tokio::test]
+    async fn ignores_play_packets_without_handlers() {
+        let mut proxy = Proxy::connect(velocity_config("secret")).await;
+
+        let (message_id, _, _) = proxy.log_in("notch").await;
+        let forwarded = forwarding::velocity_data(b"secret", 1);
+        proxy.respond(message_id, Some(&forwarded)).await;
+
+        // A chat message with a byte left over, so it wouldn't parse
+        let mut chat = BytesMut::new();
+        "Hello".to_string().write_to(&mut chat);
+        chat.extend_from_slice(&[0xFF]);
+        proxy.send(0x03, chat).await;
+
+        // A keep alive the server never sent, which does get the player kicked
+        let mut keep_alive = BytesMut::new();
+        (-1i64).write_to(&mut keep_alive);
+        proxy.send(0x10, keep_alive).await;
+
+        let disconnect = loop {
+            let packet = proxy.receive().await;
+            if packet.packet_id() == 0x19 {
+                break packet;
+            }
+        };
+        let reason = String::read_from_sized(&mut disconnect.data(), MAX_STRING_LENGTH).unwrap();
+        assert!(reason.contains("disconnect.timeout"));
+    }
+
+    #[tokio::test]
+    async fn kicks_clients_that_take_too_long_to_log_in() {
+        let config = Config {
+            login_timeout: Duration::from_millis(100),
+            ..Config::default()
+        };
+        let mut proxy = Proxy::connect(config).await;
+
+        proxy.handshake().await;
+
+        assert!(proxy
+            .disconnect_reason()
+            .await
+            .contains("multiplayer.disconnect.slow_login"));
+        assert_eq!(proxy.limits.rejected(Rejection::TimedOut), 1);
+    }
+
+    #[tokio::test]
+    async fn slow_status_pings_are_not_counted_as_timed_out() {
+        let config = Config {
+            login_timeout: Duration::from_millis(100),
+            ..Config::default()
+        };
+        let mut proxy = Proxy::connect(config).await;
+
+        proxy.handshake_to(1).await;
+
+        assert!(proxy.reader.next().await.is_none());
+        assert_eq!(proxy.limits.rejected_total(), 0);
+    }
+}
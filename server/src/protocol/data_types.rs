@@ -6,6 +6,8 @@ use bytes::{Buf, BufMut, BytesMut};
 
 use uuid::Uuid;
 
+pub mod nbt;
+
 pub type Result<T> = std::result::Result<T, DataTypeError>;
 
 // TODO resarch rust documentation best practices...
@@ -58,7 +60,7 @@ impl Error for DataTypeError {
 }
 
 impl DataTypeError {
-    fn add_context(self, context: impl Into<String>) -> DataTypeError {
+    pub fn add_context(self, context: impl Into<String>) -> DataTypeError {
         DataTypeError::Context(Box::new(self), context.into())
     }
 }
@@ -73,11 +75,7 @@ impl DataType for bool {
     }
 
     fn write_to(self, dst: &mut BytesMut) {
-        if self {
-            dst.put_u8(0);
-        } else {
-            dst.put_u8(1);
-        }
+        dst.put_u8(self as u8);
     }
 
     fn size(&self) -> usize {
@@ -169,7 +167,7 @@ pub type Int = i32;
 
 impl DataType for Int {
     fn read_from(src: &mut BytesMut) -> Result<Int> {
-        if src.remaining() >= 2 {
+        if src.remaining() >= 4 {
             Ok(src.get_i32())
         } else {
             Err(DataTypeError::OutOfBytes("Int".to_string()))
@@ -189,7 +187,7 @@ pub type Long = i64;
 
 impl DataType for Long {
     fn read_from(src: &mut BytesMut) -> Result<Long> {
-        if src.remaining() >= 2 {
+        if src.remaining() >= 8 {
             Ok(src.get_i64())
         } else {
             Err(DataTypeError::OutOfBytes("Long".to_string()))
@@ -209,7 +207,7 @@ pub type Float = f32;
 
 impl DataType for Float {
     fn read_from(src: &mut BytesMut) -> Result<Float> {
-        if src.remaining() >= 2 {
+        if src.remaining() >= 4 {
             Ok(src.get_f32())
         } else {
             Err(DataTypeError::OutOfBytes("Float".to_string()))
@@ -229,7 +227,7 @@ pub type Double = f64;
 
 impl DataType for Double {
     fn read_from(src: &mut BytesMut) -> Result<Double> {
-        if src.remaining() >= 2 {
+        if src.remaining() >= 8 {
             Ok(src.get_f64())
         } else {
             Err(DataTypeError::OutOfBytes("Double".to_string()))
@@ -241,7 +239,7 @@ impl DataType for Double {
     }
 
     fn size(&self) -> usize {
-        4
+        8
     }
 }
 
@@ -262,25 +260,39 @@ impl SizedDataType for String {
             )
         })?;
 
-        if length > size {
+        // Like vanilla, the size is in characters, each of which can take up
+        // to 4 bytes.
+        if length > size * 4 {
             return Err(DataTypeError::Malformed(
                 "String".to_string(),
                 format!("length header too large for string of max size {}", size),
             ));
         }
 
-        if src.remaining() >= length {
-            let data = src.split_to(length);
+        if src.remaining() < length {
+            return Err(DataTypeError::OutOfBytes("String".to_string()));
+        }
 
-            Ok(String::from_utf8(data.as_ref().into()).map_err(|e| {
-                DataTypeError::Malformed(
-                    "String".to_string(),
-                    format!("malformed UTF8 string: {}", e),
-                )
-            })?)
-        } else {
-            Err(DataTypeError::OutOfBytes("String".to_string()))
+        let data = src.split_to(length);
+        let string = String::from_utf8(data.as_ref().into()).map_err(|e| {
+            DataTypeError::Malformed(
+                "String".to_string(),
+                format!("malformed UTF8 string: {}", e),
+            )
+        })?;
+
+        let chars = string.chars().count();
+        if chars > size {
+            return Err(DataTypeError::Malformed(
+                "String".to_string(),
+                format!(
+                    "string of {} characters longer than max size of {}",
+                    chars, size
+                ),
+            ));
         }
+
+        Ok(string)
     }
 
     fn write_to(self, dst: &mut BytesMut) {
@@ -304,6 +316,22 @@ impl Chat {
     pub fn new(message: String) -> Chat {
         Chat { message }
     }
+
+    /// A plain text chat component.
+    pub fn text(text: &str) -> Chat {
+        Chat::new(serde_json::json!({ "text": text }).to_string())
+    }
+
+    /// A chat component translated by the client, with `with` filling in the
+    /// translation's placeholders.
+    pub fn translate(key: &str, with: &[&str]) -> Chat {
+        Chat::new(serde_json::json!({ "translate": key, "with": with }).to_string())
+    }
+
+    /// The chat component as JSON.
+    pub fn as_str(&self) -> &str {
+        &self.message
+    }
 }
 
 impl DataType for Chat {
@@ -322,7 +350,7 @@ impl DataType for Chat {
     }
 }
 
-// TODO
+// TODO validate the namespace and path
 pub struct Identifier {
     identifier: String,
 }
@@ -331,6 +359,10 @@ impl Identifier {
     pub fn new(identifier: String) -> Identifier {
         Identifier { identifier }
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.identifier
+    }
 }
 
 impl DataType for Identifier {
@@ -349,7 +381,7 @@ impl DataType for Identifier {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct VarInt {
     value: i32,
 }
@@ -363,15 +395,13 @@ impl VarInt {
         self.value
     }
 
-    /// Reads a var int from the input buffer, being careful to leave the buffer
-    /// alone in the case of failure. This is useful when reading packet headers
-    /// that might have a partially loaded VarInt.
-    pub fn careful_read_from(src: &mut BytesMut) -> Result<VarInt> {
+    /// Reads a var int from the start of the input without consuming it,
+    /// returning its value and how many bytes it took up. This is useful when
+    /// reading packet headers that might have a partially loaded VarInt.
+    pub fn peek(src: &[u8]) -> Result<(VarInt, usize)> {
         let mut result = 0;
 
-        // Get an iterator over the bytes in this stream.
-        // Comes from the bytes as a slice so there is no advancing being done.
-        for (i, byte) in src.as_ref().iter().enumerate() {
+        for (i, byte) in src.iter().enumerate() {
             // VarInts are never longer than 5 bytes
             if i + 1 > 5 {
                 return Err(DataTypeError::Malformed(
@@ -388,9 +418,7 @@ impl VarInt {
             // The high bit of every byte tells us if there's another byte to
             // decode
             if byte & 0x80 == 0 {
-                // Advance the buffer upon success
-                src.advance(i + 1);
-                return Ok(VarInt::new(result));
+                return Ok((VarInt::new(result), i + 1));
             }
         }
 
@@ -475,6 +503,7 @@ impl VarLong {
         VarLong { value }
     }
 
+    #[allow(dead_code)]
     fn value(&self) -> i64 {
         self.value
     }
@@ -555,7 +584,6 @@ pub struct Position {
 
 impl DataType for Position {
     fn read_from(src: &mut BytesMut) -> Result<Position> {
-        // TODO this needs testing
         if src.remaining() >= 8 {
             let val = src.get_u64();
 
@@ -585,24 +613,11 @@ impl DataType for Position {
     }
 
     fn write_to(self, dst: &mut BytesMut) {
-        // TODO this needs testing...
-        let mut x = self.x;
-        let mut y = self.y;
-        let mut z = self.z;
-
-        if x < 0 {
-            x += 1 << 25
-        }
-        if y < 0 {
-            y += 1 << 12
-        }
-        if z < 0 {
-            z += 1 << 26
-        }
-
-        let val = (((x & 0x3FFFFFF) as u64) << 38)
-            | (((z & 0x3FFFFFF) as u64) << 12)
-            | ((y & 0xFFF) as u64);
+        // Masking off the high bits of a negative number leaves it in two's
+        // complement with the smaller width.
+        let val = (((self.x & 0x3FFFFFF) as u64) << 38)
+            | (((self.z & 0x3FFFFFF) as u64) << 12)
+            | ((self.y & 0xFFF) as u64);
 
         dst.put_u64(val)
     }
@@ -612,6 +627,7 @@ impl DataType for Position {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Angle {
     /// The number of 1/256 steps of a full turn
@@ -656,6 +672,74 @@ impl DataType for Uuid {
     }
 }
 
+/// Optional values are prefixed with a bool saying whether they're there.
+impl<T: DataType> DataType for Option<T> {
+    fn read_from(src: &mut BytesMut) -> Result<Option<T>> {
+        if bool::read_from(src)? {
+            Ok(Some(T::read_from(src)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn write_to(self, dst: &mut BytesMut) {
+        self.is_some().write_to(dst);
+
+        if let Some(value) = self {
+            value.write_to(dst);
+        }
+    }
+
+    fn size(&self) -> usize {
+        1 + self.as_ref().map_or(0, T::size)
+    }
+}
+
+/// An inventory slot, which is empty if None.
+pub type Slot = Option<ItemStack>;
+
+pub struct ItemStack {
+    pub item_id: VarInt,
+    pub count: Byte,
+    pub nbt: Option<nbt::Nbt>,
+}
+
+impl DataType for ItemStack {
+    fn read_from(src: &mut BytesMut) -> Result<ItemStack> {
+        let item_id = VarInt::read_from(src)?;
+        let count = Byte::read_from(src)?;
+
+        // A lone end tag stands in for the NBT when there isn't any.
+        let nbt = match src.first() {
+            Some(&nbt::tag_id::END) => {
+                src.advance(1);
+                None
+            }
+            _ => Some(nbt::Nbt::read_from(src)?),
+        };
+
+        Ok(ItemStack {
+            item_id,
+            count,
+            nbt,
+        })
+    }
+
+    fn write_to(self, dst: &mut BytesMut) {
+        self.item_id.write_to(dst);
+        self.count.write_to(dst);
+
+        match self.nbt {
+            Some(nbt) => nbt.write_to(dst),
+            None => dst.put_u8(nbt::tag_id::END),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.item_id.size() + 1 + self.nbt.as_ref().map_or(1, nbt::Nbt::size)
+    }
+}
+
 impl<T: DataType> SizedDataType for Vec<T> {
     fn read_from_sized(src: &mut BytesMut, size: usize) -> Result<Vec<T>> {
         let array_size = VarInt::read_from(src)?.value() as usize;
@@ -701,13 +785,13 @@ impl<T: DataType> SizedDataType for Vec<T> {
     }
 
     fn size(&self) -> usize {
-        VarInt::new(self.len() as i32).size() + self.len()
+        VarInt::new(self.len() as i32).size() + self.iter().map(T::size).sum::<usize>()
     }
 }
 
-/// A much faster implementation for a vector of bytes but since we can't have
-/// both this and the generic implementation I've opted for the ergonomics of
-/// the generics...
+// A much faster implementation for a vector of bytes but since we can't have
+// both this and the generic implementation I've opted for the ergonomics of
+// the generics...
 // impl SizedDataType for Vec<u8> {
 //     fn read_from_sized(src: &mut BytesMut, size: usize) -> Result<ByteArray> {
 //         let array_size = VarInt::read_from(src)?.value() as usize;
@@ -745,6 +829,110 @@ impl<T: DataType> SizedDataType for Vec<T> {
 mod tests {
     use crate::protocol::data_types::*;
 
+    #[test]
+    fn short_numbers_are_out_of_bytes() {
+        let mut bytes = BytesMut::from(&[0u8; 7][..]);
+
+        assert!(matches!(
+            Long::read_from(&mut bytes),
+            Err(DataTypeError::OutOfBytes(_))
+        ));
+        assert!(matches!(
+            Double::read_from(&mut bytes),
+            Err(DataTypeError::OutOfBytes(_))
+        ));
+    }
+
+    #[test]
+    fn position_round_trip() {
+        let mut bytes = BytesMut::new();
+
+        Position {
+            x: -33554432,
+            z: 33554431,
+            y: -1,
+        }
+        .write_to(&mut bytes);
+        // From the wiki.vg protocol page
+        Position {
+            x: 18357644,
+            z: -20882616,
+            y: 831,
+        }
+        .write_to(&mut bytes);
+        assert_eq!(&bytes[8..], &0x4607632C15B4833Fu64.to_be_bytes());
+
+        let position = Position::read_from(&mut bytes).unwrap();
+        assert_eq!(
+            (position.x, position.z, position.y),
+            (-33554432, 33554431, -1)
+        );
+        let position = Position::read_from(&mut bytes).unwrap();
+        assert_eq!(
+            (position.x, position.z, position.y),
+            (18357644, -20882616, 831)
+        );
+    }
+
+    #[test]
+    fn empty_slots_round_trip() {
+        let mut bytes = BytesMut::new();
+
+        let stack = ItemStack {
+            item_id: VarInt::new(1),
+            count: 64,
+            nbt: None,
+        };
+        Some(stack).write_to(&mut bytes);
+        None::<ItemStack>.write_to(&mut bytes);
+        assert_eq!(bytes.as_ref(), &[0x01, 0x01, 64, 0x00, 0x00]);
+
+        let stack = Slot::read_from(&mut bytes).unwrap().unwrap();
+        assert_eq!((stack.item_id.value(), stack.count), (1, 64));
+        assert!(stack.nbt.is_none());
+        assert!(Slot::read_from(&mut bytes).unwrap().is_none());
+    }
+
+    #[test]
+    fn bool_round_trip() {
+        let mut bytes = BytesMut::new();
+
+        true.write_to(&mut bytes);
+        false.write_to(&mut bytes);
+        assert_eq!(bytes.as_ref(), &[0x01, 0x00]);
+
+        assert!(bool::read_from(&mut bytes).unwrap());
+        assert!(!bool::read_from(&mut bytes).unwrap());
+    }
+
+    #[test]
+    fn strings_are_limited_by_characters() {
+        let mut bytes = BytesMut::new();
+
+        // 256 characters in 512 bytes, as long as a chat message can be
+        "é".repeat(256).write_to(&mut bytes);
+        "字".repeat(4).write_to(&mut bytes);
+        "a".repeat(257).write_to(&mut bytes);
+
+        assert_eq!(
+            String::read_from_sized(&mut bytes, 256).unwrap(),
+            "é".repeat(256)
+        );
+        assert_eq!(String::read_from_sized(&mut bytes, 4).unwrap(), "字字字字");
+        assert!(matches!(
+            String::read_from_sized(&mut bytes, 256),
+            Err(DataTypeError::Malformed(_, _))
+        ));
+
+        // The header alone is too long for any 4 character string
+        let mut bytes = BytesMut::new();
+        VarInt::new(17).write_to(&mut bytes);
+        assert!(matches!(
+            String::read_from_sized(&mut bytes, 4),
+            Err(DataTypeError::Malformed(_, _))
+        ));
+    }
+
     #[test]
     fn var_int_basic_read() {
         // From the wiki.vg protocol page
@@ -844,18 +1032,17 @@ mod tests {
     }
 
     #[test]
-    fn var_int_careful_read() {
+    fn var_int_peek() {
         // Valid VarInts will end with a byte with a zero as MSB
-        let mut bytes = BytesMut::with_capacity(1);
-        bytes.extend_from_slice(&[0x80]);
-
         assert!(matches!(
-            VarInt::careful_read_from(&mut bytes),
+            VarInt::peek(&[0x80]),
             Err(DataTypeError::OutOfBytes(_))
         ));
 
-        // Shouldn't touch the buffer on failure
-        assert_eq!(bytes.len(), 1);
+        assert_eq!(
+            VarInt::peek(&[0xdd, 0xc7, 0x01, 0xff]).unwrap(),
+            (VarInt::new(25565), 3)
+        );
     }
 
     #[test]
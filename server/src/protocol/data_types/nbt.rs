@@ -0,0 +1,805 @@
+//! Named Binary Tag, the format the game uses for structured data such as the
+//! dimension codec and item data. Over the network it is big-endian and
+//! uncompressed, with a root compound tag.
+
+use std::collections::btree_map::{self, BTreeMap};
+use std::fmt;
+use std::mem;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::{DataType, DataTypeError, Result};
+
+pub mod de;
+pub mod ser;
+
+/// The most memory a single NBT value read from the network may use, as
+/// estimated by [`Reader`]. Small inputs can otherwise expand into huge trees
+/// (a list of a million empty compounds is only a few megabytes on the wire).
+pub const MAX_NBT_SIZE: usize = 2 * 1024 * 1024;
+
+/// How deeply compounds and lists can be nested, the same as vanilla.
+pub const MAX_NBT_DEPTH: usize = 512;
+
+/// The type ID of each tag, as written before tag payloads.
+pub mod tag_id {
+    pub const END: u8 = 0;
+    pub const BYTE: u8 = 1;
+    pub const SHORT: u8 = 2;
+    pub const INT: u8 = 3;
+    pub const LONG: u8 = 4;
+    pub const FLOAT: u8 = 5;
+    pub const DOUBLE: u8 = 6;
+    pub const BYTE_ARRAY: u8 = 7;
+    pub const STRING: u8 = 8;
+    pub const LIST: u8 = 9;
+    pub const COMPOUND: u8 = 10;
+    pub const INT_ARRAY: u8 = 11;
+    pub const LONG_ARRAY: u8 = 12;
+}
+
+/// A single NBT value. The End tag only marks the end of a compound, so it has
+/// no variant here.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    /// Every element of a list must be the same type of tag.
+    List(Vec<Tag>),
+    Compound(Compound),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    pub fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => tag_id::BYTE,
+            Tag::Short(_) => tag_id::SHORT,
+            Tag::Int(_) => tag_id::INT,
+            Tag::Long(_) => tag_id::LONG,
+            Tag::Float(_) => tag_id::FLOAT,
+            Tag::Double(_) => tag_id::DOUBLE,
+            Tag::ByteArray(_) => tag_id::BYTE_ARRAY,
+            Tag::String(_) => tag_id::STRING,
+            Tag::List(_) => tag_id::LIST,
+            Tag::Compound(_) => tag_id::COMPOUND,
+            Tag::IntArray(_) => tag_id::INT_ARRAY,
+            Tag::LongArray(_) => tag_id::LONG_ARRAY,
+        }
+    }
+
+    fn write_payload(&self, dst: &mut BytesMut) {
+        match self {
+            Tag::Byte(v) => dst.put_i8(*v),
+            Tag::Short(v) => dst.put_i16(*v),
+            Tag::Int(v) => dst.put_i32(*v),
+            Tag::Long(v) => dst.put_i64(*v),
+            Tag::Float(v) => dst.put_f32(*v),
+            Tag::Double(v) => dst.put_f64(*v),
+            Tag::ByteArray(v) => {
+                dst.put_i32(v.len() as i32);
+                for b in v {
+                    dst.put_i8(*b);
+                }
+            }
+            Tag::String(v) => write_string(v, dst),
+            Tag::List(v) => {
+                debug_assert!(
+                    v.iter().all(|tag| tag.id() == v[0].id()),
+                    "NBT list elements must all be the same type"
+                );
+
+                dst.put_u8(v.first().map_or(tag_id::END, Tag::id));
+                dst.put_i32(v.len() as i32);
+                for tag in v {
+                    tag.write_payload(dst);
+                }
+            }
+            Tag::Compound(v) => v.write_payload(dst),
+            Tag::IntArray(v) => {
+                dst.put_i32(v.len() as i32);
+                for i in v {
+                    dst.put_i32(*i);
+                }
+            }
+            Tag::LongArray(v) => {
+                dst.put_i32(v.len() as i32);
+                for l in v {
+                    dst.put_i64(*l);
+                }
+            }
+        }
+    }
+
+    fn payload_size(&self) -> usize {
+        match self {
+            Tag::Byte(_) => 1,
+            Tag::Short(_) => 2,
+            Tag::Int(_) | Tag::Float(_) => 4,
+            Tag::Long(_) | Tag::Double(_) => 8,
+            Tag::ByteArray(v) => 4 + v.len(),
+            Tag::String(v) => string_size(v),
+            Tag::List(v) => 5 + v.iter().map(Tag::payload_size).sum::<usize>(),
+            Tag::Compound(v) => v.payload_size(),
+            Tag::IntArray(v) => 4 + v.len() * 4,
+            Tag::LongArray(v) => 4 + v.len() * 8,
+        }
+    }
+}
+
+macro_rules! impl_from_for_tag {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for Tag {
+                fn from(value: $ty) -> Tag {
+                    Tag::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from_for_tag! {
+    i8 => Byte,
+    i16 => Short,
+    i32 => Int,
+    i64 => Long,
+    f32 => Float,
+    f64 => Double,
+    Vec<i8> => ByteArray,
+    String => String,
+    &str => String,
+    Vec<Tag> => List,
+    Compound => Compound,
+    Vec<i32> => IntArray,
+    Vec<i64> => LongArray,
+}
+
+/// NBT has no boolean tag, vanilla uses a byte of 0 or 1.
+impl From<bool> for Tag {
+    fn from(value: bool) -> Tag {
+        Tag::Byte(value as i8)
+    }
+}
+
+/// A compound tag, mapping names to tags. Entries are kept sorted by name so
+/// the same compound is always written the same way.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Compound {
+    entries: BTreeMap<String, Tag>,
+}
+
+impl Compound {
+    pub fn new() -> Compound {
+        Compound::default()
+    }
+
+    /// Adds an entry, for building up compounds in a single expression:
+    ///
+    /// ```ignore
+    /// Compound::new().with("name", "minecraft:plains").with("id", 1)
+    /// ```
+    pub fn with(mut self, name: &str, value: impl Into<Tag>) -> Compound {
+        self.insert(name, value);
+        self
+    }
+
+    /// Adds an entry, returning the tag it replaced if there was one.
+    pub fn insert(&mut self, name: &str, value: impl Into<Tag>) -> Option<Tag> {
+        self.entries.insert(name.to_string(), value.into())
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        self.entries.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, String, Tag> {
+        self.entries.iter()
+    }
+
+    fn write_payload(&self, dst: &mut BytesMut) {
+        for (name, tag) in &self.entries {
+            dst.put_u8(tag.id());
+            write_string(name, dst);
+            tag.write_payload(dst);
+        }
+        dst.put_u8(tag_id::END);
+    }
+
+    fn payload_size(&self) -> usize {
+        let entries: usize = self
+            .entries
+            .iter()
+            .map(|(name, tag)| 1 + string_size(name) + tag.payload_size())
+            .sum();
+
+        entries + 1
+    }
+}
+
+impl IntoIterator for Compound {
+    type Item = (String, Tag);
+    type IntoIter = btree_map::IntoIter<String, Tag>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl std::iter::FromIterator<(String, Tag)> for Compound {
+    fn from_iter<I: IntoIterator<Item = (String, Tag)>>(iter: I) -> Compound {
+        Compound {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// A complete NBT value as sent over the network: a named root compound. The
+/// name is almost always empty.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Nbt {
+    name: String,
+    root: Compound,
+}
+
+impl Nbt {
+    pub fn new(root: Compound) -> Nbt {
+        Nbt::named(String::new(), root)
+    }
+
+    pub fn named(name: String, root: Compound) -> Nbt {
+        Nbt { name, root }
+    }
+
+    #[cfg(test)]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[cfg(test)]
+    pub fn root(&self) -> &Compound {
+        &self.root
+    }
+}
+
+impl DataType for Nbt {
+    fn read_from(src: &mut BytesMut) -> Result<Nbt> {
+        let mut reader = Reader::new(src);
+
+        match reader.read_u8()? {
+            tag_id::COMPOUND => {}
+            id => {
+                return Err(malformed(format!(
+                    "root tag must be a compound, not type {}",
+                    id
+                )))
+            }
+        }
+
+        let name = reader.read_string()?;
+        let root = reader.read_compound()?;
+
+        Ok(Nbt { name, root })
+    }
+
+    fn write_to(self, dst: &mut BytesMut) {
+        dst.put_u8(tag_id::COMPOUND);
+        write_string(&self.name, dst);
+        self.root.write_payload(dst);
+    }
+
+    fn size(&self) -> usize {
+        1 + string_size(&self.name) + self.root.payload_size()
+    }
+}
+
+/// Reads tags while keeping track of how deeply they're nested and roughly how
+/// much memory they take up, so malicious input can't exhaust either. Nested
+/// tags are read with an explicit stack rather than recursion, since hundreds
+/// of levels of recursion can overflow a task's stack.
+struct Reader<'a> {
+    src: &'a mut BytesMut,
+    /// The estimated memory used by everything read so far.
+    used: usize,
+}
+
+/// A list or compound that is part way through being read.
+enum Container {
+    List {
+        id: u8,
+        remaining: usize,
+        list: Vec<Tag>,
+    },
+    Compound {
+        compound: Compound,
+        /// The name of the entry being read.
+        name: String,
+    },
+}
+
+/// The start of a tag: either the whole tag or a container to fill in.
+enum Value {
+    Tag(Tag),
+    Container(Container),
+}
+
+impl<'a> Reader<'a> {
+    fn new(src: &'a mut BytesMut) -> Reader<'a> {
+        Reader { src, used: 0 }
+    }
+
+    fn account(&mut self, bytes: usize) -> Result<()> {
+        self.used = self.used.saturating_add(bytes);
+
+        if self.used > MAX_NBT_SIZE {
+            Err(malformed(format!(
+                "tag is larger than the maximum of {} bytes",
+                MAX_NBT_SIZE
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn ensure(&self, bytes: usize, what: &str) -> Result<()> {
+        if self.src.remaining() >= bytes {
+            Ok(())
+        } else {
+            Err(DataTypeError::OutOfBytes(format!("NBT {}", what)))
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        self.ensure(1, "tag type")?;
+        Ok(self.src.get_u8())
+    }
+
+    /// Reads the length of an array or list, checking the elements could
+    /// actually fit in the rest of the input before anything is allocated.
+    fn read_length(&mut self, element_size: usize, what: &str) -> Result<usize> {
+        self.ensure(4, what)?;
+        let length = self.src.get_i32();
+
+        if length < 0 {
+            return Err(malformed(format!("negative {} length {}", what, length)));
+        }
+
+        let length = length as usize;
+        self.ensure(length.saturating_mul(element_size), what)?;
+
+        Ok(length)
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        self.ensure(2, "string length")?;
+        let length = self.src.get_u16() as usize;
+
+        self.ensure(length, "string")?;
+        self.account(length)?;
+
+        let bytes = self.src.split_to(length);
+        decode_modified_utf8(&bytes)
+    }
+
+    /// Reads the payload of a compound, including its End tag.
+    fn read_compound(&mut self) -> Result<Compound> {
+        let mut stack = vec![Container::Compound {
+            compound: Compound::new(),
+            name: String::new(),
+        }];
+
+        loop {
+            // Find the type of the next tag in the innermost container, if
+            // it has any left.
+            let next = match stack.last_mut().unwrap() {
+                Container::List { id, remaining, .. } => {
+                    if *remaining > 0 {
+                        *remaining -= 1;
+                        Some(*id)
+                    } else {
+                        None
+                    }
+                }
+                Container::Compound { name, .. } => match self.read_u8()? {
+                    tag_id::END => None,
+                    id => {
+                        *name = self.read_string()?;
+                        Some(id)
+                    }
+                },
+            };
+
+            let tag = match next {
+                Some(id) => match self.read_value(id)? {
+                    Value::Tag(tag) => tag,
+                    Value::Container(container) => {
+                        if stack.len() > MAX_NBT_DEPTH {
+                            return Err(malformed(format!(
+                                "tags nested deeper than the maximum of {}",
+                                MAX_NBT_DEPTH
+                            )));
+                        }
+
+                        stack.push(container);
+                        continue;
+                    }
+                },
+                None => match stack.pop().unwrap() {
+                    Container::List { list, .. } => Tag::List(list),
+                    Container::Compound { compound, .. } => {
+                        if stack.is_empty() {
+                            return Ok(compound);
+                        }
+                        Tag::Compound(compound)
+                    }
+                },
+            };
+
+            match stack.last_mut().unwrap() {
+                Container::List { list, .. } => list.push(tag),
+                Container::Compound { compound, name } => {
+                    compound.entries.insert(mem::take(name), tag);
+                }
+            }
+        }
+    }
+
+    fn read_value(&mut self, id: u8) -> Result<Value> {
+        self.account(mem::size_of::<Tag>())?;
+
+        let tag = match id {
+            tag_id::BYTE => {
+                self.ensure(1, "byte")?;
+                Tag::Byte(self.src.get_i8())
+            }
+            tag_id::SHORT => {
+                self.ensure(2, "short")?;
+                Tag::Short(self.src.get_i16())
+            }
+            tag_id::INT => {
+                self.ensure(4, "int")?;
+                Tag::Int(self.src.get_i32())
+            }
+            tag_id::LONG => {
+                self.ensure(8, "long")?;
+                Tag::Long(self.src.get_i64())
+            }
+            tag_id::FLOAT => {
+                self.ensure(4, "float")?;
+                Tag::Float(self.src.get_f32())
+            }
+            tag_id::DOUBLE => {
+                self.ensure(8, "double")?;
+                Tag::Double(self.src.get_f64())
+            }
+            tag_id::BYTE_ARRAY => {
+                let length = self.read_length(1, "byte array")?;
+                self.account(length)?;
+                Tag::ByteArray((0..length).map(|_| self.src.get_i8()).collect())
+            }
+            tag_id::STRING => Tag::String(self.read_string()?),
+            tag_id::LIST => {
+                let id = self.read_u8()?;
+                // Every element is at least a byte, except for the End tags
+                // of an empty list.
+                let length = self.read_length(1, "list")?;
+
+                if id == tag_id::END && length > 0 {
+                    return Err(malformed("list of End tags".to_string()));
+                }
+
+                return Ok(Value::Container(Container::List {
+                    id,
+                    remaining: length,
+                    list: Vec::new(),
+                }));
+            }
+            tag_id::COMPOUND => {
+                return Ok(Value::Container(Container::Compound {
+                    compound: Compound::new(),
+                    name: String::new(),
+                }))
+            }
+            tag_id::INT_ARRAY => {
+                let length = self.read_length(4, "int array")?;
+                self.account(length * 4)?;
+                Tag::IntArray((0..length).map(|_| self.src.get_i32()).collect())
+            }
+            tag_id::LONG_ARRAY => {
+                let length = self.read_length(8, "long array")?;
+                self.account(length * 8)?;
+                Tag::LongArray((0..length).map(|_| self.src.get_i64()).collect())
+            }
+            id => return Err(malformed(format!("unknown tag type {}", id))),
+        };
+
+        Ok(Value::Tag(tag))
+    }
+}
+
+impl fmt::Display for Tag {
+    /// Formats the tag as SNBT, the text format used in commands.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_list<T: fmt::Display>(
+            f: &mut fmt::Formatter<'_>,
+            prefix: &str,
+            items: &[T],
+            suffix: &str,
+        ) -> fmt::Result {
+            write!(f, "[{}", prefix)?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}{}", item, suffix)?;
+            }
+            write!(f, "]")
+        }
+
+        match self {
+            Tag::Byte(v) => write!(f, "{}b", v),
+            Tag::Short(v) => write!(f, "{}s", v),
+            Tag::Int(v) => write!(f, "{}", v),
+            Tag::Long(v) => write!(f, "{}L", v),
+            Tag::Float(v) => write!(f, "{}f", v),
+            Tag::Double(v) => write!(f, "{}d", v),
+            Tag::ByteArray(v) => write_list(f, "B;", v, "b"),
+            Tag::String(v) => write!(f, "{:?}", v),
+            Tag::List(v) => write_list(f, "", v, ""),
+            Tag::Compound(v) => {
+                write!(f, "{{")?;
+                for (i, (name, tag)) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{:?}:{}", name, tag)?;
+                }
+                write!(f, "}}")
+            }
+            Tag::IntArray(v) => write_list(f, "I;", v, ""),
+            Tag::LongArray(v) => write_list(f, "L;", v, "L"),
+        }
+    }
+}
+
+/// An error converting between Rust types and NBT with serde.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+fn malformed(reason: String) -> DataTypeError {
+    DataTypeError::Malformed("NBT".to_string(), reason)
+}
+
+/// The longest string NBT can hold, in bytes of modified UTF-8.
+const MAX_STRING_LENGTH: usize = u16::MAX as usize;
+
+fn write_string(s: &str, dst: &mut BytesMut) {
+    let length = string_size(s) - 2;
+    debug_assert!(
+        length <= MAX_STRING_LENGTH,
+        "NBT strings can't be longer than {} bytes",
+        MAX_STRING_LENGTH
+    );
+
+    dst.put_u16(length as u16);
+    encode_modified_utf8(s, dst);
+}
+
+fn string_size(s: &str) -> usize {
+    let encoded: usize = s
+        .encode_utf16()
+        .map(|unit| match unit {
+            0x0001..=0x007F => 1,
+            0x0000 | 0x0080..=0x07FF => 2,
+            _ => 3,
+        })
+        .sum();
+
+    2 + encoded
+}
+
+/// Encodes a string the way Java's `DataOutput.writeUTF` does: UTF-8, except
+/// that nulls take two bytes and characters outside the basic multilingual
+/// plane are written as surrogate pairs.
+fn encode_modified_utf8(s: &str, dst: &mut BytesMut) {
+    for unit in s.encode_utf16() {
+        match unit {
+            0x0001..=0x007F => dst.put_u8(unit as u8),
+            0x0000 | 0x0080..=0x07FF => {
+                dst.put_u8(0xC0 | (unit >> 6) as u8);
+                dst.put_u8(0x80 | (unit & 0x3F) as u8);
+            }
+            _ => {
+                dst.put_u8(0xE0 | (unit >> 12) as u8);
+                dst.put_u8(0x80 | ((unit >> 6) & 0x3F) as u8);
+                dst.put_u8(0x80 | (unit & 0x3F) as u8);
+            }
+        }
+    }
+}
+
+fn decode_modified_utf8(bytes: &[u8]) -> Result<String> {
+    let bad_string = || malformed("string is not valid modified UTF-8".to_string());
+    let continuation = |byte: Option<&u8>| match byte {
+        Some(byte) if byte & 0xC0 == 0x80 => Ok((byte & 0x3F) as u16),
+        _ => Err(bad_string()),
+    };
+
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let byte = bytes[i];
+
+        let (unit, length) = match byte {
+            0x01..=0x7F => (byte as u16, 1),
+            0xC0..=0xDF => {
+                let unit = ((byte & 0x1F) as u16) << 6 | continuation(bytes.get(i + 1))?;
+                (unit, 2)
+            }
+            0xE0..=0xEF => {
+                let unit = ((byte & 0x0F) as u16) << 12
+                    | continuation(bytes.get(i + 1))? << 6
+                    | continuation(bytes.get(i + 2))?;
+                (unit, 3)
+            }
+            _ => return Err(bad_string()),
+        };
+
+        units.push(unit);
+        i += length;
+    }
+
+    // Java strings can hold unpaired surrogates, Rust ones can't.
+    Ok(String::from_utf16_lossy(&units))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(nbt: Nbt) -> BytesMut {
+        let size = nbt.size();
+        let mut data = BytesMut::with_capacity(size);
+        nbt.write_to(&mut data);
+        assert_eq!(data.len(), size);
+        data
+    }
+
+    #[test]
+    fn reads_hello_world() {
+        // hello_world.nbt from the NBT specification
+        let mut data =
+            BytesMut::from(&b"\x0a\x00\x0bhello world\x08\x00\x04name\x00\x09Bananrama\x00"[..]);
+
+        let nbt = Nbt::read_from(&mut data).unwrap();
+
+        assert!(data.is_empty());
+        assert_eq!(nbt.name(), "hello world");
+        assert_eq!(nbt.root(), &Compound::new().with("name", "Bananrama"));
+    }
+
+    #[test]
+    fn every_tag_round_trips() {
+        let root = Compound::new()
+            .with("byte", 1i8)
+            .with("short", -2i16)
+            .with("int", 3)
+            .with("long", -4i64)
+            .with("float", 5.5f32)
+            .with("double", -6.25)
+            .with("byte array", vec![1i8, -1])
+            .with("string", "null \0 and \u{1F600}")
+            .with("list", vec![Tag::Int(1), Tag::Int(2)])
+            .with("empty list", Vec::<Tag>::new())
+            .with("compound", Compound::new().with("bool", true))
+            .with("int array", vec![7, 8, 9])
+            .with("long array", vec![10i64]);
+        let nbt = Nbt::new(root);
+
+        let mut data = write(nbt.clone());
+        assert_eq!(Nbt::read_from(&mut data).unwrap(), nbt);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn strings_are_modified_utf8() {
+        let mut data = BytesMut::new();
+        write_string("a\0\u{1F600}", &mut data);
+
+        assert_eq!(
+            &data[..],
+            &[0, 9, b'a', 0xC0, 0x80, 0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80]
+        );
+        assert_eq!(decode_modified_utf8(&data[2..]).unwrap(), "a\0\u{1F600}");
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let data = write(Nbt::new(Compound::new().with("long", 1i64)));
+
+        for length in 0..data.len() {
+            let mut truncated = BytesMut::from(&data[..length]);
+            assert!(Nbt::read_from(&mut truncated).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        // Lists of lists, each holding one element
+        let nested = |depth: usize| {
+            let mut data = BytesMut::from(&b"\x0a\x00\x00\x09\x00\x01a"[..]);
+            for _ in 1..depth {
+                data.extend_from_slice(&[tag_id::LIST, 0, 0, 0, 1]);
+            }
+            data.extend_from_slice(&[tag_id::END, 0, 0, 0, 0, 0]);
+            data
+        };
+
+        assert!(Nbt::read_from(&mut nested(MAX_NBT_DEPTH)).is_ok());
+        assert!(Nbt::read_from(&mut nested(MAX_NBT_DEPTH + 1)).is_err());
+    }
+
+    #[test]
+    fn rejects_huge_lengths() {
+        // An int array claiming two billion elements
+        let mut data = BytesMut::from(&b"\x0a\x00\x00\x0b\x00\x01a\x7f\xff\xff\xff"[..]);
+        assert!(Nbt::read_from(&mut data).is_err());
+    }
+
+    #[test]
+    fn rejects_expansion_bombs() {
+        // A list of empty compounds costs one byte each on the wire but far
+        // more in memory.
+        let count = MAX_NBT_SIZE;
+        let mut data = BytesMut::from(&b"\x0a\x00\x00\x09\x00\x01a\x0a"[..]);
+        data.put_i32(count as i32);
+        data.extend_from_slice(&vec![0u8; count]);
+        data.put_u8(0);
+
+        assert!(Nbt::read_from(&mut data).is_err());
+    }
+
+    #[test]
+    fn formats_as_snbt() {
+        let tag = Tag::Compound(
+            Compound::new()
+                .with("a", vec![Tag::Byte(1)])
+                .with("b", vec![2i64, 3]),
+        );
+
+        assert_eq!(tag.to_string(), r#"{"a":[1b],"b":[L;2L,3L]}"#);
+    }
+}
@@ -0,0 +1,251 @@
+//! Deserializing Rust types from NBT with serde, the reverse of [`super::ser`].
+
+use serde::de::value::{MapDeserializer, SeqDeserializer, StringDeserializer};
+use serde::de::{self, DeserializeSeed, EnumAccess, IntoDeserializer, VariantAccess, Visitor};
+use serde::forward_to_deserialize_any;
+
+use super::{Error, Tag};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Deserializes a value from a tag.
+#[cfg(test)]
+pub fn from_tag<T: de::DeserializeOwned>(tag: Tag) -> Result<T> {
+    T::deserialize(tag)
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Tag {
+    type Deserializer = Tag;
+
+    fn into_deserializer(self) -> Tag {
+        self
+    }
+}
+
+fn visit_seq<'de, V, I>(visitor: V, iter: I) -> Result<V::Value>
+where
+    V: Visitor<'de>,
+    I: Iterator,
+    I::Item: IntoDeserializer<'de, Error>,
+{
+    let mut seq = SeqDeserializer::new(iter);
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+}
+
+impl<'de> de::Deserializer<'de> for Tag {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Tag::Byte(v) => visitor.visit_i8(v),
+            Tag::Short(v) => visitor.visit_i16(v),
+            Tag::Int(v) => visitor.visit_i32(v),
+            Tag::Long(v) => visitor.visit_i64(v),
+            Tag::Float(v) => visitor.visit_f32(v),
+            Tag::Double(v) => visitor.visit_f64(v),
+            Tag::ByteArray(v) => visit_seq(visitor, v.into_iter()),
+            Tag::String(v) => visitor.visit_string(v),
+            Tag::List(v) => visit_seq(visitor, v.into_iter()),
+            Tag::Compound(v) => {
+                let mut map = MapDeserializer::new(v.into_iter());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            Tag::IntArray(v) => visit_seq(visitor, v.into_iter()),
+            Tag::LongArray(v) => visit_seq(visitor, v.into_iter()),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Tag::Byte(v) => visitor.visit_bool(v != 0),
+            tag => tag.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        // Missing entries are None, anything that's there is Some.
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self {
+            Tag::String(variant) => {
+                let variant: StringDeserializer<Error> = variant.into_deserializer();
+                visitor.visit_enum(variant)
+            }
+            Tag::Compound(compound) if compound.len() == 1 => {
+                let (variant, value) = compound.into_iter().next().unwrap();
+                visitor.visit_enum(Variant { variant, value })
+            }
+            tag => Err(Error(format!("expected an enum but got {}", tag))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// An enum variant with data, stored as a compound holding just the variant.
+struct Variant {
+    variant: String,
+    value: Tag,
+}
+
+impl<'de> EnumAccess<'de> for Variant {
+    type Error = Error;
+    type Variant = Tag;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Tag)> {
+        let variant: StringDeserializer<Error> = self.variant.into_deserializer();
+        Ok((seed.deserialize(variant)?, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Tag {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Err(Error("unit variants are written as strings".to_string()))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::super::ser::{to_compound, to_tag};
+    use super::super::{Compound, Tag};
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Dimension {
+        name: String,
+        id: i32,
+        piglin_safe: bool,
+        coordinate_scale: f64,
+        fixed_time: Option<i64>,
+        effects: Effects,
+        heights: Vec<u16>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Effects {
+        Overworld,
+        Custom { sky_color: i32 },
+    }
+
+    fn dimension() -> Dimension {
+        Dimension {
+            name: "minecraft:overworld".to_string(),
+            id: 0,
+            piglin_safe: false,
+            coordinate_scale: 1.0,
+            fixed_time: None,
+            effects: Effects::Custom { sky_color: 7907327 },
+            heights: vec![0, 256],
+        }
+    }
+
+    #[test]
+    fn structs_serialize_to_compounds() {
+        let compound = to_compound(&dimension()).unwrap();
+
+        assert_eq!(
+            compound,
+            Compound::new()
+                .with("name", "minecraft:overworld")
+                .with("id", 0)
+                .with("piglin_safe", false)
+                .with("coordinate_scale", 1.0)
+                .with(
+                    "effects",
+                    Compound::new().with("Custom", Compound::new().with("sky_color", 7907327))
+                )
+                .with("heights", vec![Tag::Int(0), Tag::Int(256)])
+        );
+    }
+
+    #[test]
+    fn structs_round_trip() {
+        let tag = to_tag(&dimension()).unwrap();
+
+        assert_eq!(from_tag::<Dimension>(tag).unwrap(), dimension());
+    }
+
+    #[test]
+    fn tags_serialize_as_themselves() {
+        #[derive(Serialize)]
+        struct Wrapper {
+            array: Tag,
+        }
+
+        let wrapper = Wrapper {
+            array: Tag::IntArray(vec![1, 2]),
+        };
+
+        assert_eq!(
+            to_compound(&wrapper).unwrap(),
+            Compound::new().with("array", vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn unit_variants_are_strings() {
+        assert_eq!(
+            to_tag(&Effects::Overworld).unwrap(),
+            Tag::String("Overworld".to_string())
+        );
+        assert_eq!(
+            from_tag::<Effects>(Tag::String("Overworld".to_string())).unwrap(),
+            Effects::Overworld
+        );
+    }
+
+    #[test]
+    fn mixed_lists_are_rejected() {
+        #[derive(Serialize)]
+        #[serde(untagged)]
+        enum Either {
+            Int(i32),
+            Text(&'static str),
+        }
+
+        assert!(to_tag(&vec![Either::Int(1), Either::Text("two")]).is_err());
+    }
+}
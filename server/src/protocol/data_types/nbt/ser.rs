@@ -0,0 +1,583 @@
+//! Serializing Rust types into NBT with serde.
+//!
+//! Structs and maps become compounds, sequences become lists and `None` fields
+//! are left out. Integers keep their width, with unsigned integers widened to
+//! the next signed tag since NBT has none of its own. Enum variants without
+//! data are written as their name, others as a compound holding just the
+//! variant.
+
+use serde::ser::{self, Impossible, Serialize};
+
+use super::{Compound, Error, Tag};
+
+/// Newtype struct names that mark a sequence as an int or long array rather
+/// than a list. Used by the `Serialize` implementation for [`Tag`].
+const INT_ARRAY: &str = "$nbt:IntArray";
+const LONG_ARRAY: &str = "$nbt:LongArray";
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Serializes a value into a tag.
+pub fn to_tag<T: Serialize + ?Sized>(value: &T) -> Result<Tag> {
+    value
+        .serialize(Serializer)?
+        .ok_or_else(|| Error("can't serialize None as NBT".to_string()))
+}
+
+/// Serializes a value that becomes a compound, such as a struct or map.
+pub fn to_compound<T: Serialize + ?Sized>(value: &T) -> Result<Compound> {
+    match to_tag(value)? {
+        Tag::Compound(compound) => Ok(compound),
+        tag => Err(Error(format!("expected a compound but got {}", tag))),
+    }
+}
+
+/// Serializes a single value. Produces None for `None`, so that compounds can
+/// leave the entry out.
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    type SerializeSeq = ListSerializer;
+    type SerializeTuple = ListSerializer;
+    type SerializeTupleStruct = ListSerializer;
+    type SerializeTupleVariant = VariantSerializer<ListSerializer>;
+    type SerializeMap = CompoundSerializer;
+    type SerializeStruct = CompoundSerializer;
+    type SerializeStructVariant = VariantSerializer<CompoundSerializer>;
+
+    fn serialize_bool(self, v: bool) -> Result<Option<Tag>> {
+        Ok(Some(v.into()))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Option<Tag>> {
+        Ok(Some(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Option<Tag>> {
+        Ok(Some(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Option<Tag>> {
+        Ok(Some(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Option<Tag>> {
+        Ok(Some(v.into()))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Short(v.into())))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Int(v.into())))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Long(v.into())))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Option<Tag>> {
+        if v <= i64::MAX as u64 {
+            Ok(Some(Tag::Long(v as i64)))
+        } else {
+            Err(Error(format!("{} is too large for a long tag", v)))
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Option<Tag>> {
+        Ok(Some(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Option<Tag>> {
+        Ok(Some(v.into()))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Option<Tag>> {
+        Ok(Some(Tag::String(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Option<Tag>> {
+        Ok(Some(v.into()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Option<Tag>> {
+        Ok(Some(Tag::ByteArray(v.iter().map(|b| *b as i8).collect())))
+    }
+
+    fn serialize_none(self) -> Result<Option<Tag>> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Option<Tag>> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Compound(Compound::new())))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<Tag>> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Option<Tag>> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Option<Tag>> {
+        let tag = to_tag(value)?;
+
+        let array = match name {
+            INT_ARRAY => Some(Tag::IntArray(array_elements(tag, |tag| match tag {
+                Tag::Int(v) => Some(v),
+                _ => None,
+            })?)),
+            LONG_ARRAY => Some(Tag::LongArray(array_elements(tag, |tag| match tag {
+                Tag::Long(v) => Some(v),
+                _ => None,
+            })?)),
+            _ => return Ok(Some(tag)),
+        };
+
+        Ok(array)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Compound(
+            Compound::new().with(variant, to_tag(value)?),
+        )))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ListSerializer> {
+        Ok(ListSerializer {
+            list: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ListSerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<ListSerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<ListSerializer>> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<CompoundSerializer> {
+        Ok(CompoundSerializer {
+            compound: Compound::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<CompoundSerializer> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<CompoundSerializer>> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+fn array_elements<T>(tag: Tag, element: impl Fn(Tag) -> Option<T>) -> Result<Vec<T>> {
+    let list = match tag {
+        Tag::List(list) => list,
+        tag => return Err(Error(format!("expected an array but got {}", tag))),
+    };
+
+    list.into_iter()
+        .map(|tag| element(tag).ok_or_else(|| Error("mixed types in array".to_string())))
+        .collect()
+}
+
+struct ListSerializer {
+    list: Vec<Tag>,
+}
+
+impl ser::SerializeSeq for ListSerializer {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let tag = to_tag(value)?;
+
+        if let Some(first) = self.list.first() {
+            if first.id() != tag.id() {
+                return Err(Error(format!(
+                    "list elements must all be the same type, got {} after {}",
+                    tag, first
+                )));
+            }
+        }
+
+        self.list.push(tag);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        Ok(Some(Tag::List(self.list)))
+    }
+}
+
+impl ser::SerializeTuple for ListSerializer {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for ListSerializer {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct CompoundSerializer {
+    compound: Compound,
+    /// The key of the entry whose value is being serialized next.
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for CompoundSerializer {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error("map value without a key".to_string()))?;
+
+        if let Some(tag) = value.serialize(Serializer)? {
+            self.compound.insert(&key, tag);
+        }
+
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        Ok(Some(Tag::Compound(self.compound)))
+    }
+}
+
+impl ser::SerializeStruct for CompoundSerializer {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        if let Some(tag) = value.serialize(Serializer)? {
+            self.compound.insert(key, tag);
+        }
+
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+/// Wraps the value of an enum variant in a compound holding just the variant.
+struct VariantSerializer<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> VariantSerializer<S> {
+    fn wrap(variant: &str, inner: Option<Tag>) -> Result<Option<Tag>> {
+        Ok(inner.map(|inner| Tag::Compound(Compound::new().with(variant, inner))))
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<ListSerializer> {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        Self::wrap(self.variant, ser::SerializeSeq::end(self.inner)?)
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<CompoundSerializer> {
+    type Ok = Option<Tag>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Option<Tag>> {
+        Self::wrap(self.variant, ser::SerializeMap::end(self.inner)?)
+    }
+}
+
+/// Compound entries are named by strings, so map keys have to be strings too.
+struct KeySerializer;
+
+fn key_error() -> Error {
+    Error("compound keys must be strings".to_string())
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<String, Error>;
+    type SerializeTuple = Impossible<String, Error>;
+    type SerializeTupleStruct = Impossible<String, Error>;
+    type SerializeTupleVariant = Impossible<String, Error>;
+    type SerializeMap = Impossible<String, Error>;
+    type SerializeStruct = Impossible<String, Error>;
+    type SerializeStructVariant = Impossible<String, Error>;
+
+    fn serialize_str(self, v: &str) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_none(self) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_unit(self) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String> {
+        Err(key_error())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(key_error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_error())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(key_error())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(key_error())
+    }
+}
+
+/// Tags serialize as themselves, so they can be embedded in serialized
+/// structs. With other serializers arrays come out as plain sequences.
+impl Serialize for Tag {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use ser::{SerializeMap, SerializeSeq};
+
+        match self {
+            Tag::Byte(v) => serializer.serialize_i8(*v),
+            Tag::Short(v) => serializer.serialize_i16(*v),
+            Tag::Int(v) => serializer.serialize_i32(*v),
+            Tag::Long(v) => serializer.serialize_i64(*v),
+            Tag::Float(v) => serializer.serialize_f32(*v),
+            Tag::Double(v) => serializer.serialize_f64(*v),
+            Tag::ByteArray(v) => {
+                let bytes: Vec<u8> = v.iter().map(|b| *b as u8).collect();
+                serializer.serialize_bytes(&bytes)
+            }
+            Tag::String(v) => serializer.serialize_str(v),
+            Tag::List(v) => {
+                let mut seq = serializer.serialize_seq(Some(v.len()))?;
+                for tag in v {
+                    seq.serialize_element(tag)?;
+                }
+                seq.end()
+            }
+            Tag::Compound(v) => {
+                let mut map = serializer.serialize_map(Some(v.len()))?;
+                for (name, tag) in v.iter() {
+                    map.serialize_entry(name, tag)?;
+                }
+                map.end()
+            }
+            Tag::IntArray(v) => serializer.serialize_newtype_struct(INT_ARRAY, v),
+            Tag::LongArray(v) => serializer.serialize_newtype_struct(LONG_ARRAY, v),
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use bytes::BytesMut;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use uuid::Uuid;
+
+use crate::api::{GameProfile, ProfileProperty};
+use crate::protocol::data_types::{DataType, SizedDataType, VarInt, MAX_STRING_LENGTH};
+
+/// The login plugin channel Velocity forwards player details on.
+pub const VELOCITY_CHANNEL: &str = "velocity:player_info";
+
+/// The version of Velocity's forwarding the server asks for, the original one.
+/// Later versions add chat signing keys, which 1.16 doesn't have.
+pub const VELOCITY_FORWARDING_VERSION: u8 = 1;
+
+/// The length of the HMAC-SHA256 signature at the start of Velocity's data.
+const VELOCITY_SIGNATURE_SIZE: usize = 32;
+
+/// How a proxy in front of the server passes on the details of players
+/// connecting through it. Players are trusted to be who the proxy says, so the
+/// server must only be reachable through the proxy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ForwardingMode {
+    /// Players connect directly.
+    None,
+    /// BungeeCord's IP forwarding, which adds the player's details to the
+    /// server address in the handshake.
+    BungeeCord,
+    /// Velocity's modern forwarding, which sends the player's details in a
+    /// login plugin response signed with a secret shared with the proxy.
+    Velocity,
+}
+
+impl ForwardingMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            ForwardingMode::None => "none",
+            ForwardingMode::BungeeCord => "bungeecord",
+            ForwardingMode::Velocity => "velocity",
+        }
+    }
+}
+
+impl fmt::Display for ForwardingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ForwardingMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ForwardingMode, ()> {
+        match s {
+            "none" => Ok(ForwardingMode::None),
+            "bungeecord" => Ok(ForwardingMode::BungeeCord),
+            "velocity" => Ok(ForwardingMode::Velocity),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The details of a player as forwarded by a proxy.
+#[derive(Debug)]
+pub struct Forwarded {
+    /// The address the player connected to the proxy from.
+    pub address: IpAddr,
+    pub uuid: Uuid,
+    /// The player's name, if the proxy sends it.
+    pub name: Option<String>,
+    /// The player's profile properties, such as their skin.
+    pub properties: Vec<ProfileProperty>,
+}
+
+impl Forwarded {
+    /// Reads the details BungeeCord adds to the server address in the
+    /// handshake. The hostname is followed by the player's IP, their UUID
+    /// without dashes and, if the proxy is in online mode, their profile
+    /// properties as JSON, all separated by null characters.
+    pub fn from_bungeecord(server_address: &str) -> Result<Forwarded> {
+        let parts: Vec<&str> = server_address.split('\0').collect();
+
+        let (address, uuid, properties) = match parts[..] {
+            [_, address, uuid] => (address, uuid, None),
+            [_, address, uuid, properties] => (address, uuid, Some(properties)),
+            _ => return Err(anyhow!("Server address has no forwarded data")),
+        };
+
+        let address = address
+            .parse()
+            .map_err(|_| anyhow!("Bad forwarded address {:?}", address))?;
+        let uuid = Uuid::parse_str(uuid).context("Bad forwarded UUID")?;
+        let properties = match properties {
+            Some(properties) => {
+                serde_json::from_str(properties).context("Bad forwarded properties")?
+            }
+            None => Vec::new(),
+        };
+
+        Ok(Forwarded {
+            address,
+            uuid,
+            name: None,
+            properties,
+        })
+    }
+
+    /// Reads the details Velocity sends in answer to a login plugin request on
+    /// `VELOCITY_CHANNEL`, checking they were signed with `secret`.
+    pub fn from_velocity(data: &[u8], secret: &[u8]) -> Result<Forwarded> {
+        if data.len() < VELOCITY_SIGNATURE_SIZE {
+            return Err(anyhow!("Forwarded data is too short to be signed"));
+        }
+
+        let (signature, payload) = data.split_at(VELOCITY_SIGNATURE_SIZE);
+
+        let key = PKey::hmac(secret)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(payload)?;
+        if !memcmp::eq(&signer.sign_to_vec()?, signature) {
+            return Err(anyhow!("Forwarded data has a bad signature"));
+        }
+
+        let mut payload = BytesMut::from(payload);
+
+        let version = VarInt::read_from(&mut payload)?.value();
+        if version < 1 || version > VELOCITY_FORWARDING_VERSION as i32 {
+            return Err(anyhow!("Unsupported forwarding version {}", version));
+        }
+
+        let address = String::read_from_sized(&mut payload, 255)?;
+        let address = address
+            .parse()
+            .map_err(|_| anyhow!("Bad forwarded address {:?}", address))?;
+        let uuid = Uuid::read_from(&mut payload)?;
+        let name = String::read_from_sized(&mut payload, 16)?;
+
+        let count = VarInt::read_from(&mut payload)?.value();
+        let mut properties = Vec::new();
+        for _ in 0..count {
+            let name = String::read_from_sized(&mut payload, MAX_STRING_LENGTH)?;
+            let value = String::read_from_sized(&mut payload, MAX_STRING_LENGTH)?;
+            let signature = if bool::read_from(&mut payload)? {
+                Some(String::read_from_sized(&mut payload, MAX_STRING_LENGTH)?)
+            } else {
+                None
+            };
+
+            properties.push(ProfileProperty {
+                name,
+                value,
+                signature,
+            });
+        }
+
+        if !payload.is_empty() {
+            return Err(anyhow!("Forwarded data has {} extra bytes", payload.len()));
+        }
+
+        Ok(Forwarded {
+            address,
+            uuid,
+            name: Some(name),
+            properties,
+        })
+    }
+
+    /// The player's profile. When the proxy doesn't forward the player's name
+    /// the one they logged in with is used.
+    pub fn into_profile(self, username: &str) -> GameProfile {
+        GameProfile {
+            uuid: self.uuid,
+            name: self.name.unwrap_or_else(|| username.to_string()),
+            properties: self.properties,
+        }
+    }
+}
+
+/// Velocity's data for a player, as signed by the proxy. Shared by the tests
+/// here and in `connection`.
+#[cfg(test)]
+pub fn velocity_data(secret: &[u8], version: i32) -> Vec<u8> {
+    let mut payload = BytesMut::new();
+    VarInt::new(version).write_to(&mut payload);
+    "203.0.113.7".to_string().write_to(&mut payload);
+    Uuid::parse_str("069a79f444e94726a5befca90e38aaf5")
+        .unwrap()
+        .write_to(&mut payload);
+    "Notch".to_string().write_to(&mut payload);
+    VarInt::new(1).write_to(&mut payload);
+    "textures".to_string().write_to(&mut payload);
+    "e30=".to_string().write_to(&mut payload);
+    true.write_to(&mut payload);
+    "c2ln".to_string().write_to(&mut payload);
+
+    let key = PKey::hmac(secret).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(&payload).unwrap();
+
+    let mut data = signer.sign_to_vec().unwrap();
+    data.extend_from_slice(&payload);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bungeecord_data() {
+        let address = [
+            "play.example.com",
+            "203.0.113.7",
+            "069a79f444e94726a5befca90e38aaf5",
+            r#"[{"name":"textures","value":"e30=","signature":"c2ln"}]"#,
+        ]
+        .join("\0");
+
+        let forwarded = Forwarded::from_bungeecord(&address).unwrap();
+        assert_eq!(forwarded.address, "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            forwarded.uuid,
+            Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        );
+
+        let profile = forwarded.into_profile("Notch");
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(profile.properties.len(), 1);
+        assert_eq!(profile.properties[0].name, "textures");
+        assert_eq!(profile.properties[0].signature.as_deref(), Some("c2ln"));
+    }
+
+    #[test]
+    fn properties_are_optional() {
+        let address = ["localhost", "::1", "069a79f444e94726a5befca90e38aaf5"].join("\0");
+
+        let forwarded = Forwarded::from_bungeecord(&address).unwrap();
+        assert_eq!(forwarded.address, "::1".parse::<IpAddr>().unwrap());
+        assert!(forwarded.properties.is_empty());
+    }
+
+    #[test]
+    fn rejects_addresses_without_forwarding() {
+        assert!(Forwarded::from_bungeecord("localhost").is_err());
+        assert!(Forwarded::from_bungeecord("localhost\0FML\0").is_err());
+        let address = ["localhost", "not an ip", "069a79f444e94726a5befca90e38aaf5"].join("\0");
+        assert!(Forwarded::from_bungeecord(&address).is_err());
+    }
+
+    #[test]
+    fn parses_velocity_data() {
+        let data = velocity_data(b"secret", 1);
+
+        let profile = Forwarded::from_velocity(&data, b"secret")
+            .unwrap()
+            .into_profile("notch");
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(
+            profile.uuid,
+            Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+        );
+        assert_eq!(profile.properties[0].value, "e30=");
+        assert_eq!(profile.properties[0].signature.as_deref(), Some("c2ln"));
+    }
+
+    #[test]
+    fn rejects_badly_signed_velocity_data() {
+        let data = velocity_data(b"secret", 1);
+        assert!(Forwarded::from_velocity(&data, b"not the secret").is_err());
+
+        let mut tampered = data.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(Forwarded::from_velocity(&tampered, b"secret").is_err());
+
+        assert!(Forwarded::from_velocity(&data[..16], b"secret").is_err());
+    }
+
+    #[test]
+    fn rejects_newer_velocity_versions() {
+        let data = velocity_data(b"secret", 2);
+
+        assert!(Forwarded::from_velocity(&data, b"secret").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+/// How often a keep alive is sent to players, and how long they have to answer
+/// it. Same as vanilla.
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// What to do when the next keep alive is due.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Tick {
+    /// Send a keep alive with this ID.
+    Send(i64),
+    /// The client never answered the last one.
+    TimedOut,
+}
+
+/// Tracks the keep alives sent to a player in the play state, and measures
+/// their latency from how long the answers take.
+pub struct KeepAlive {
+    /// The ID of the keep alive the client hasn't answered yet, and when it was
+    /// sent.
+    pending: Option<(i64, Instant)>,
+    latency: Duration,
+}
+
+impl KeepAlive {
+    pub fn new() -> KeepAlive {
+        KeepAlive {
+            pending: None,
+            latency: Duration::from_millis(0),
+        }
+    }
+
+    /// Called every `KEEP_ALIVE_INTERVAL`.
+    pub fn tick(&mut self, now: Instant) -> Tick {
+        if self.pending.is_some() {
+            return Tick::TimedOut;
+        }
+
+        let id = rand::random();
+        self.pending = Some((id, now));
+
+        Tick::Send(id)
+    }
+
+    /// Called when the client answers a keep alive. Returns false if it wasn't
+    /// the one that was sent, which vanilla treats as a timeout.
+    pub fn received(&mut self, id: i64, now: Instant) -> bool {
+        match self.pending {
+            Some((pending_id, sent)) if pending_id == id => {
+                self.pending = None;
+
+                // A moving average like vanilla's, so one slow answer doesn't
+                // make the ping jump around.
+                let elapsed = now.saturating_duration_since(sent);
+                self.latency = (self.latency * 3 + elapsed) / 4;
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The player's average latency, as shown in the tab list.
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent_id(tick: Tick) -> i64 {
+        match tick {
+            Tick::Send(id) => id,
+            Tick::TimedOut => panic!("timed out"),
+        }
+    }
+
+    #[test]
+    fn answered_keep_alives_measure_latency() {
+        let mut keep_alive = KeepAlive::new();
+        let start = Instant::now();
+
+        let id = sent_id(keep_alive.tick(start));
+        assert!(keep_alive.received(id, start + Duration::from_millis(100)));
+        assert_eq!(keep_alive.latency(), Duration::from_millis(25));
+
+        let later = start + KEEP_ALIVE_INTERVAL;
+        let id = sent_id(keep_alive.tick(later));
+        assert!(keep_alive.received(id, later + Duration::from_millis(125)));
+        assert_eq!(keep_alive.latency(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn unanswered_keep_alives_time_out() {
+        let mut keep_alive = KeepAlive::new();
+        let start = Instant::now();
+
+        sent_id(keep_alive.tick(start));
+        assert_eq!(keep_alive.tick(start + KEEP_ALIVE_INTERVAL), Tick::TimedOut);
+    }
+
+    #[test]
+    fn unexpected_ids_are_rejected() {
+        let mut keep_alive = KeepAlive::new();
+        let start = Instant::now();
+
+        // Nothing has been sent yet
+        assert!(!keep_alive.received(0, start));
+
+        let id = sent_id(keep_alive.tick(start));
+        assert!(!keep_alive.received(id.wrapping_add(1), start));
+        // Answering twice doesn't count either
+        assert!(keep_alive.received(id, start));
+        assert!(!keep_alive.received(id, start));
+    }
+}
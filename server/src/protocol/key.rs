@@ -0,0 +1,152 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use openssl::pkey::Private;
+use openssl::rsa::{Padding, Rsa};
+
+/// The smallest key size clients accept.
+pub const MIN_KEY_SIZE: u32 = 1024;
+
+/// The largest key size, which bounds how long encrypted data in the
+/// encryption response can be.
+pub const MAX_KEY_SIZE: u32 = 4096;
+
+/// The server's RSA key pair, which clients use to send the shared secret
+/// when they log in.
+pub struct ServerKey {
+    rsa: Rsa<Private>,
+    /// The public key as sent to clients, which is also part of the server
+    /// hash. Worked out once since it's needed for every login.
+    public_key_der: Vec<u8>,
+}
+
+impl ServerKey {
+    pub fn new(rsa: Rsa<Private>) -> Result<ServerKey> {
+        let bits = rsa.size() * 8;
+        if !(MIN_KEY_SIZE..=MAX_KEY_SIZE).contains(&bits) {
+            return Err(anyhow!(
+                "Key is {} bits but must be between {} and {}",
+                bits,
+                MIN_KEY_SIZE,
+                MAX_KEY_SIZE
+            ));
+        }
+
+        let public_key_der = rsa.public_key_to_der()?;
+
+        Ok(ServerKey {
+            rsa,
+            public_key_der,
+        })
+    }
+
+    pub fn generate(bits: u32) -> Result<ServerKey> {
+        info!("Generating a {} bit server key", bits);
+
+        ServerKey::new(Rsa::generate(bits)?)
+    }
+
+    /// Parses a private key in PKCS #1 or PKCS #8 PEM format.
+    pub fn from_pem(pem: &[u8]) -> Result<ServerKey> {
+        ServerKey::new(Rsa::private_key_from_pem(pem)?)
+    }
+
+    /// The private key in PKCS #1 PEM format.
+    pub fn to_pem(&self) -> Result<Vec<u8>> {
+        Ok(self.rsa.private_key_to_pem()?)
+    }
+
+    /// Loads the key from the PEM file at `path`. If there isn't one a new key
+    /// is generated and saved there, so the server keeps the same key between
+    /// restarts.
+    pub fn load_or_generate(path: &Path, bits: u32) -> Result<ServerKey> {
+        match fs::read(path) {
+            Ok(pem) => {
+                let key = ServerKey::from_pem(&pem)
+                    .with_context(|| format!("Invalid key in {}", path.display()))?;
+                info!("Loaded server key from {}", path.display());
+                Ok(key)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let key = ServerKey::generate(bits)?;
+                write_private(path, &key.to_pem()?)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                info!("Saved server key to {}", path.display());
+                Ok(key)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// The public key in DER format.
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key_der
+    }
+
+    /// Decrypts data a client encrypted with the public key.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut decrypted = vec![0; self.rsa.size() as usize];
+        let len = self
+            .rsa
+            .private_decrypt(data, &mut decrypted, Padding::PKCS1)?;
+        decrypted.truncate(len);
+
+        Ok(decrypted)
+    }
+}
+
+/// Writes a file only the current user can read, where that's supported.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_survive_being_saved() {
+        let path = std::env::temp_dir().join(format!("mcserver-key-{}.pem", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let generated = ServerKey::load_or_generate(&path, 2048).unwrap();
+        let loaded = ServerKey::load_or_generate(&path, 1024).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(generated.public_key_der(), loaded.public_key_der());
+        assert_eq!(loaded.rsa.size(), 256);
+    }
+
+    #[test]
+    fn decrypts_what_the_public_key_encrypts() {
+        let key = ServerKey::generate(1024).unwrap();
+        let public = Rsa::public_key_from_der(key.public_key_der()).unwrap();
+
+        let mut encrypted = vec![0; public.size() as usize];
+        public
+            .public_encrypt(b"shared secret!!!", &mut encrypted, Padding::PKCS1)
+            .unwrap();
+
+        assert_eq!(key.decrypt(&encrypted).unwrap(), b"shared secret!!!");
+        assert!(key.decrypt(b"not encrypted").is_err());
+    }
+
+    #[test]
+    fn rejects_small_keys() {
+        let pem = Rsa::generate(512).unwrap().private_key_to_pem().unwrap();
+
+        assert!(ServerKey::from_pem(&pem).is_err());
+    }
+}
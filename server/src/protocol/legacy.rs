@@ -0,0 +1,277 @@
+use std::convert::TryInto;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::time::timeout;
+
+use crate::protocol::packets::status;
+
+/// How long to wait for the rest of a legacy ping after the 0xFE byte. Beta
+/// clients only ever send the one byte, so this decides which format is used.
+const LEGACY_PING_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The most bytes read from a legacy ping. A 1.6 ping with the longest allowed
+/// hostname fits comfortably within this.
+const MAX_LEGACY_PING_SIZE: usize = 1024;
+
+/// Vanilla reports this protocol version to legacy clients so they always show
+/// the server as incompatible.
+const LEGACY_PROTOCOL_VERSION: i32 = 127;
+
+/// The kinds of server list ping sent by clients from before the switch to
+/// Netty (1.6 and older). These don't use VarInt framing at all, they start
+/// with a single 0xFE byte and are answered with a kick packet.
+#[derive(Debug, PartialEq)]
+pub enum LegacyPing {
+    /// Beta 1.8 to 1.3, just the 0xFE byte.
+    Beta,
+    /// 1.4 and 1.5, 0xFE followed by 0x01.
+    V1_4,
+    /// 1.6, which adds an `MC|PingHost` plugin message.
+    V1_6 {
+        protocol_version: u8,
+        hostname: String,
+        port: i32,
+    },
+}
+
+/// Checks whether a connection starts with a legacy ping, without consuming
+/// any data. A modern handshake never starts with 0xFE.
+pub async fn starts_with_legacy_ping(src: &mut OwnedReadHalf) -> Result<bool> {
+    let mut first = [0u8; 1];
+    let num_read = src.peek(&mut first).await?;
+
+    Ok(num_read == 1 && first[0] == 0xFE)
+}
+
+/// Reads a legacy ping from the start of a connection.
+pub async fn read_legacy_ping<R: AsyncRead + Unpin>(src: &mut R) -> Result<LegacyPing> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 256];
+
+    while buffer.len() < MAX_LEGACY_PING_SIZE {
+        if let Some(ping) = parse_legacy_ping(&buffer) {
+            return Ok(ping);
+        }
+
+        match timeout(LEGACY_PING_TIMEOUT, src.read(&mut chunk)).await {
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(num_read)) => buffer.extend_from_slice(&chunk[..num_read]),
+            Ok(Err(e)) => return Err(e.into()),
+        }
+    }
+
+    // Nothing more arrived, so the client sent as much as it's going to.
+    Ok(match buffer.get(1) {
+        Some(0x01) => LegacyPing::V1_4,
+        _ => LegacyPing::Beta,
+    })
+}
+
+/// Parses a complete 1.6 ping (the only variant that can be recognised before
+/// the client goes quiet). Returns None if the ping is incomplete or isn't a
+/// 1.6 ping.
+fn parse_legacy_ping(buffer: &[u8]) -> Option<LegacyPing> {
+    let mut rest = buffer.strip_prefix(&[0xFE, 0x01, 0xFA])?;
+
+    if read_legacy_string(&mut rest)? != "MC|PingHost" {
+        return None;
+    }
+
+    let _data_length = read_u16(&mut rest)?;
+    let protocol_version = *rest.first()?;
+    rest = &rest[1..];
+    let hostname = read_legacy_string(&mut rest)?;
+    let port = i32::from_be_bytes(rest.get(..4)?.try_into().ok()?);
+
+    Some(LegacyPing::V1_6 {
+        protocol_version,
+        hostname,
+        port,
+    })
+}
+
+fn read_u16(src: &mut &[u8]) -> Option<u16> {
+    let value = u16::from_be_bytes(src.get(..2)?.try_into().ok()?);
+    *src = &src[2..];
+    Some(value)
+}
+
+/// Reads a string prefixed with its length in UTF-16 code units.
+fn read_legacy_string(src: &mut &[u8]) -> Option<String> {
+    let length = read_u16(src)? as usize;
+    let bytes = src.get(..length * 2)?;
+    *src = &src[length * 2..];
+
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+        .collect();
+
+    Some(String::from_utf16_lossy(&units))
+}
+
+/// Builds the kick packet that answers a legacy ping, using the same data as
+/// a modern status response.
+pub fn legacy_ping_response(ping: &LegacyPing, status: &status::Response) -> Vec<u8> {
+    let motd = component_text(status.description());
+
+    let message = match ping {
+        // Beta clients split the message on `§`, so the MOTD can't have any
+        // formatting codes.
+        LegacyPing::Beta => format!(
+            "{}\u{a7}{}\u{a7}{}",
+            strip_formatting(&motd),
+            status.players_online(),
+            status.players_max()
+        ),
+        LegacyPing::V1_4 | LegacyPing::V1_6 { .. } => format!(
+            "\u{a7}1\0{}\0{}\0{}\0{}\0{}",
+            LEGACY_PROTOCOL_VERSION,
+            status.version_name(),
+            motd,
+            status.players_online(),
+            status.players_max()
+        ),
+    };
+
+    let units: Vec<u16> = message.encode_utf16().collect();
+
+    let mut packet = Vec::with_capacity(3 + units.len() * 2);
+    packet.push(0xFF);
+    packet.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    for unit in units {
+        packet.extend_from_slice(&unit.to_be_bytes());
+    }
+
+    packet
+}
+
+/// Flattens a chat component down to its plain text.
+fn component_text(component: &Value) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Array(components) => components.iter().map(component_text).collect(),
+        Value::Object(fields) => {
+            let mut text = fields
+                .get("text")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+
+            if let Some(Value::Array(extra)) = fields.get("extra") {
+                text.extend(extra.iter().map(component_text));
+            }
+
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+/// Removes legacy `§` formatting codes from a string.
+fn strip_formatting(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\u{a7}' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::protocol::version::ProtocolVersion;
+
+    fn legacy_string(s: &str) -> Vec<u8> {
+        let units: Vec<u16> = s.encode_utf16().collect();
+        let mut bytes = (units.len() as u16).to_be_bytes().to_vec();
+        for unit in units {
+            bytes.extend_from_slice(&unit.to_be_bytes());
+        }
+        bytes
+    }
+
+    fn decode_kick(packet: &[u8]) -> String {
+        assert_eq!(packet[0], 0xFF);
+        let mut rest = &packet[1..];
+        let message = read_legacy_string(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        message
+    }
+
+    fn status() -> status::Response {
+        status::Response::new(
+            ProtocolVersion::V1_16_4,
+            20,
+            3,
+            Vec::new(),
+            json!({"text": "\u{a7}aHello ", "extra": [{"text": "World"}]}),
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn reads_beta_ping() {
+        let mut src: &[u8] = &[0xFE];
+        assert_eq!(read_legacy_ping(&mut src).await.unwrap(), LegacyPing::Beta);
+    }
+
+    #[tokio::test]
+    async fn reads_1_4_ping() {
+        let mut src: &[u8] = &[0xFE, 0x01];
+        assert_eq!(read_legacy_ping(&mut src).await.unwrap(), LegacyPing::V1_4);
+    }
+
+    #[tokio::test]
+    async fn reads_1_6_ping() {
+        let hostname = legacy_string("localhost");
+
+        let mut ping = vec![0xFE, 0x01, 0xFA];
+        ping.extend(legacy_string("MC|PingHost"));
+        ping.extend(&(7 + hostname.len() as u16 - 2).to_be_bytes());
+        ping.push(78);
+        ping.extend(hostname);
+        ping.extend(&25565i32.to_be_bytes());
+
+        let mut src: &[u8] = &ping;
+        assert_eq!(
+            read_legacy_ping(&mut src).await.unwrap(),
+            LegacyPing::V1_6 {
+                protocol_version: 78,
+                hostname: "localhost".to_string(),
+                port: 25565
+            }
+        );
+    }
+
+    #[test]
+    fn beta_response() {
+        let packet = legacy_ping_response(&LegacyPing::Beta, &status());
+
+        assert_eq!(decode_kick(&packet), "Hello World\u{a7}3\u{a7}20");
+    }
+
+    #[test]
+    fn modern_legacy_response() {
+        let packet = legacy_ping_response(&LegacyPing::V1_4, &status());
+
+        assert_eq!(
+            decode_kick(&packet),
+            "\u{a7}1\u{0}127\u{0}MC Server 1.16.5\u{0}\u{a7}aHello World\u{0}3\u{0}20"
+        );
+    }
+}
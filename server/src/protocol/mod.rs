@@ -1,4 +1,11 @@
+pub mod cipher;
 pub mod codec;
 pub mod connection;
 pub mod data_types;
+pub mod forwarding;
+pub mod keep_alive;
+pub mod key;
+pub mod legacy;
 pub mod packets;
+pub mod proxy_protocol;
+pub mod version;
@@ -1,21 +1,45 @@
-use anyhow::{anyhow, Result};
+use crate::protocol::data_types::{UnsignedShort, VarInt};
 
-use crate::protocol::data_types::{DataType, SizedDataType, UnsignedShort, VarInt};
-use crate::protocol::packets::{FromPacket, ServerboundPacket};
-
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, DataType)]
+#[discriminant(VarInt)]
 pub enum NextState {
-    Status,
-    Login,
+    Status = 1,
+    Login = 2,
 }
 
+#[derive(FromPacket)]
+#[packet_id = 0x00]
 pub struct Handshake {
     protocol_version: VarInt,
+    #[max_len = 255]
     server_address: String,
     server_port: UnsignedShort,
     next_state: NextState,
 }
 
+/// The handshake as sent by a BungeeCord proxy, which adds the player's
+/// details to the server address so allows it to be much longer.
+#[derive(FromPacket)]
+#[packet_id = 0x00]
+pub struct ForwardedHandshake {
+    protocol_version: VarInt,
+    #[max_len = 32767]
+    server_address: String,
+    server_port: UnsignedShort,
+    next_state: NextState,
+}
+
+impl From<ForwardedHandshake> for Handshake {
+    fn from(handshake: ForwardedHandshake) -> Handshake {
+        Handshake {
+            protocol_version: handshake.protocol_version,
+            server_address: handshake.server_address,
+            server_port: handshake.server_port,
+            next_state: handshake.next_state,
+        }
+    }
+}
+
 impl Handshake {
     pub fn protocol_version(&self) -> VarInt {
         self.protocol_version
@@ -34,19 +58,95 @@ impl Handshake {
     }
 }
 
-impl FromPacket for Handshake {
-    fn from_packet(packet: ServerboundPacket) -> Result<Handshake> {
-        let mut buf = packet.data();
+serverbound_packets! {
+    pub enum Serverbound {
+        Handshake(Handshake),
+    }
+}
+
+serverbound_packets! {
+    /// The packets in the handshaking state when behind a BungeeCord proxy.
+    pub enum ForwardedServerbound {
+        Handshake(ForwardedHandshake),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BytesMut;
+
+    use crate::protocol::data_types::{DataType, SizedDataType};
+    use crate::protocol::packets::ServerboundPacket;
+
+    fn handshake_data() -> BytesMut {
+        let mut data = BytesMut::new();
+        VarInt::new(753).write_to(&mut data);
+        "localhost".to_string().write_to(&mut data);
+        data.extend_from_slice(&25565u16.to_be_bytes());
+        VarInt::new(2).write_to(&mut data);
+        data
+    }
+
+    #[test]
+    fn parses_handshake() {
+        let handshake: Handshake = ServerboundPacket::new(0x00, handshake_data())
+            .parse()
+            .unwrap();
+
+        assert_eq!(handshake.protocol_version().value(), 753);
+        assert_eq!(handshake.server_address(), "localhost");
+        assert!(matches!(handshake.next_state(), NextState::Login));
+    }
+
+    #[test]
+    fn rejects_unknown_next_state() {
+        let mut data = handshake_data();
+        let len = data.len();
+        data[len - 1] = 3;
+
+        assert!(ServerboundPacket::new(0x00, data)
+            .parse::<Handshake>()
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut data = handshake_data();
+        data.extend_from_slice(&[0x00]);
+
+        assert!(ServerboundPacket::new(0x00, data)
+            .parse::<Handshake>()
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_long_address() {
+        let mut data = BytesMut::new();
+        VarInt::new(753).write_to(&mut data);
+        "a".repeat(256).write_to(&mut data);
+        data.extend_from_slice(&25565u16.to_be_bytes());
+        VarInt::new(2).write_to(&mut data);
+
+        assert!(ServerboundPacket::new(0x00, data)
+            .parse::<Handshake>()
+            .is_err());
+    }
+
+    #[test]
+    fn forwarded_handshakes_allow_long_addresses() {
+        let mut data = BytesMut::new();
+        VarInt::new(753).write_to(&mut data);
+        "a".repeat(2000).write_to(&mut data);
+        data.extend_from_slice(&25565u16.to_be_bytes());
+        VarInt::new(2).write_to(&mut data);
+
+        let handshake: Handshake = ServerboundPacket::new(0x00, data)
+            .parse::<ForwardedHandshake>()
+            .unwrap()
+            .into();
 
-        Ok(Handshake {
-            protocol_version: VarInt::read_from(&mut buf)?,
-            server_address: String::read_from_sized(&mut buf, 255)?,
-            server_port: UnsignedShort::read_from(&mut buf)?,
-            next_state: match VarInt::read_from(&mut buf)?.value() {
-                1 => NextState::Status,
-                2 => NextState::Login,
-                s => return Err(anyhow!("Unknown next state {}", s)),
-            },
-        })
+        assert_eq!(handshake.server_address().len(), 2000);
     }
 }
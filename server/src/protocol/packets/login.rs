@@ -1,12 +1,27 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use log::trace;
 use uuid::Uuid;
 
-use crate::protocol::data_types::{DataType, SizedDataType};
-use crate::protocol::packets::{ClientboundPacket, FromPacket, IntoPacket, ServerboundPacket};
+use crate::protocol::data_types::{Chat, DataType, Identifier, SizedDataType, VarInt};
+use crate::protocol::packets::{
+    ClientboundPacket, FromPacket, IntoPacket, PacketId, ServerboundPacket,
+};
 
+/// The most data a client can answer a login plugin request with, same as
+/// vanilla.
+pub const MAX_PLUGIN_RESPONSE_SIZE: usize = 1048576;
+
+#[derive(Constructor, IntoPacket)]
+#[packet_id = 0x00]
+pub struct Disconnect {
+    reason: Chat,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x00]
 pub struct Start {
+    #[max_len = 16]
     username: String,
 }
 
@@ -16,16 +31,6 @@ impl Start {
     }
 }
 
-impl FromPacket for Start {
-    fn from_packet(packet: ServerboundPacket) -> Result<Self> {
-        let mut data = packet.data();
-
-        Ok(Start {
-            username: String::read_from_sized(&mut data, 16)?,
-        })
-    }
-}
-
 pub struct EncryptionRequest {
     server_id: String, // Always empty...
     public_key: Vec<u8>,
@@ -56,8 +61,14 @@ impl IntoPacket for EncryptionRequest {
     }
 }
 
+/// Both fields are encrypted with the server's public key, so are as long as
+/// the largest key allowed.
+#[derive(FromPacket)]
+#[packet_id = 0x01]
 pub struct EncryptionResponse {
+    #[max_len = 512]
     shared_secret: Vec<u8>,
+    #[max_len = 512]
     verify_token: Vec<u8>,
 }
 
@@ -67,17 +78,6 @@ impl EncryptionResponse {
     }
 }
 
-impl FromPacket for EncryptionResponse {
-    fn from_packet(packet: ServerboundPacket) -> Result<EncryptionResponse> {
-        let mut data = packet.data();
-
-        Ok(EncryptionResponse {
-            shared_secret: Vec::<u8>::read_from_sized(&mut data, 128)?,
-            verify_token: Vec::<u8>::read_from_sized(&mut data, 128)?,
-        })
-    }
-}
-
 pub struct Success {
     uuid: Uuid,
     username: String,
@@ -86,7 +86,7 @@ pub struct Success {
 impl Success {
     pub fn new(uuid: &Uuid, username: &str) -> Success {
         Success {
-            uuid: uuid.clone(),
+            uuid: *uuid,
             username: username.to_string(),
         }
     }
@@ -101,3 +101,127 @@ impl IntoPacket for Success {
         ClientboundPacket::new(0x02, data)
     }
 }
+
+#[derive(Constructor, IntoPacket)]
+#[packet_id = 0x03]
+pub struct SetCompression {
+    threshold: VarInt,
+}
+
+/// Asks the client something on a plugin channel before it logs in. Vanilla
+/// clients always answer that they don't understand, but proxies use this to
+/// pass on details about the player.
+pub struct PluginRequest {
+    message_id: VarInt,
+    channel: Identifier,
+    /// Everything after the channel, in whatever format the channel uses.
+    data: Vec<u8>,
+}
+
+impl PluginRequest {
+    pub fn new(message_id: i32, channel: &str, data: Vec<u8>) -> PluginRequest {
+        PluginRequest {
+            message_id: VarInt::new(message_id),
+            channel: Identifier::new(channel.to_string()),
+            data,
+        }
+    }
+}
+
+impl IntoPacket for PluginRequest {
+    fn into_packet(self) -> ClientboundPacket {
+        let mut data =
+            BytesMut::with_capacity(self.message_id.size() + self.channel.size() + self.data.len());
+        self.message_id.write_to(&mut data);
+        self.channel.write_to(&mut data);
+        data.extend_from_slice(&self.data);
+
+        ClientboundPacket::new(0x04, data)
+    }
+}
+
+/// The client's answer to a `PluginRequest`.
+pub struct PluginResponse {
+    message_id: VarInt,
+    /// None if the client didn't understand the request.
+    data: Option<BytesMut>,
+}
+
+impl PluginResponse {
+    pub fn into_parts(self) -> (i32, Option<BytesMut>) {
+        (self.message_id.value(), self.data)
+    }
+}
+
+impl PacketId for PluginResponse {
+    const PACKET_ID: i32 = 0x02;
+}
+
+impl FromPacket for PluginResponse {
+    fn from_packet(packet: ServerboundPacket) -> Result<PluginResponse> {
+        let mut data = packet.data();
+
+        let message_id = VarInt::read_from(&mut data)
+            .map_err(|e| e.add_context("While reading PluginResponse::message_id"))?;
+        let successful = bool::read_from(&mut data)
+            .map_err(|e| e.add_context("While reading PluginResponse::successful"))?;
+
+        if !successful {
+            if !data.is_empty() {
+                return Err(anyhow!("Unsuccessful plugin response has data"));
+            }
+
+            return Ok(PluginResponse {
+                message_id,
+                data: None,
+            });
+        }
+
+        if data.len() > MAX_PLUGIN_RESPONSE_SIZE {
+            return Err(anyhow!(
+                "Plugin response is {} bytes, more than the max of {}",
+                data.len(),
+                MAX_PLUGIN_RESPONSE_SIZE
+            ));
+        }
+
+        Ok(PluginResponse {
+            message_id,
+            data: Some(data),
+        })
+    }
+}
+
+serverbound_packets! {
+    pub enum Serverbound {
+        Start(Start),
+        EncryptionResponse(EncryptionResponse),
+        PluginResponse(PluginResponse),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(successful: bool, extra: &[u8]) -> Result<PluginResponse> {
+        let mut data = BytesMut::new();
+        VarInt::new(7).write_to(&mut data);
+        successful.write_to(&mut data);
+        data.extend_from_slice(extra);
+
+        ServerboundPacket::new(0x02, data).parse()
+    }
+
+    #[test]
+    fn plugin_responses_keep_their_data() {
+        let (message_id, data) = response(true, b"details").unwrap().into_parts();
+        assert_eq!(message_id, 7);
+        assert_eq!(data.as_deref(), Some(&b"details"[..]));
+
+        let (_, data) = response(false, b"").unwrap().into_parts();
+        assert!(data.is_none());
+
+        assert!(response(false, b"details").is_err());
+    }
+}
@@ -1,6 +1,50 @@
 use anyhow::Result;
 use bytes::BytesMut;
 
+/// Declares an enum of the serverbound packets in a state, one variant per
+/// packet type, along with the table used to parse them by ID. Attributes apply
+/// to both. This has to come before the packet modules for them to be able to
+/// use it.
+macro_rules! serverbound_packets {
+    (
+        $(#[$attr:meta])*
+        pub enum $name:ident {
+            $($variant:ident($packet:ty),)*
+        }
+    ) => {
+        $(#[$attr])*
+        pub enum $name {
+            $($variant($packet),)*
+        }
+
+        $(#[$attr])*
+        impl $name {
+            /// The ID of every packet in the state, to check none are repeated
+            /// and to tell the packets without a handler yet from unknown ones.
+            #[allow(dead_code)]
+            pub const IDS: &'static [i32] = &[
+                $(<$packet as crate::protocol::packets::PacketId>::PACKET_ID,)*
+            ];
+
+            /// Parses a packet as whichever type has its ID, returning None if
+            /// there isn't one.
+            pub fn parse(
+                packet: crate::protocol::packets::ServerboundPacket,
+            ) -> anyhow::Result<Option<$name>> {
+                let id = packet.packet_id();
+
+                $(
+                    if id == <$packet as crate::protocol::packets::PacketId>::PACKET_ID {
+                        return Ok(Some($name::$variant(packet.parse()?)));
+                    }
+                )*
+
+                Ok(None)
+            }
+        }
+    };
+}
+
 pub mod handshake;
 pub mod login;
 pub mod play;
@@ -55,6 +99,114 @@ pub trait FromPacket: Sized {
     fn from_packet(packet: ServerboundPacket) -> Result<Self>;
 }
 
+/// The ID of a serverbound packet, derived along with `FromPacket` from its
+/// `#[packet_id]`.
+pub trait PacketId {
+    const PACKET_ID: i32;
+}
+
 pub trait IntoPacket: Sized {
     fn into_packet(self) -> ClientboundPacket;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::data_types::{DataType, SizedDataType, VarInt};
+
+    #[derive(Debug, PartialEq, DataType)]
+    #[discriminant(Byte)]
+    enum Action {
+        Add {
+            #[max_len = 16]
+            name: String,
+            count: VarInt,
+        },
+        Remove(VarInt),
+        #[value = 5]
+        Clear,
+    }
+
+    #[derive(Debug, PartialEq, FromPacket, IntoPacket)]
+    #[packet_id = 0x42]
+    #[discriminant(VarInt)]
+    enum Update {
+        #[value = 2]
+        Action(Action),
+        Reset,
+    }
+
+    #[test]
+    fn packet_ids_are_unique_in_each_state() {
+        for ids in &[
+            handshake::Serverbound::IDS,
+            handshake::ForwardedServerbound::IDS,
+            status::Serverbound::IDS,
+            login::Serverbound::IDS,
+            play::Serverbound::IDS,
+        ] {
+            let mut unique = ids.to_vec();
+            unique.sort_unstable();
+            unique.dedup();
+
+            assert_eq!(unique.len(), ids.len());
+        }
+    }
+
+    fn round_trip(update: Update) -> Update {
+        let packet = update.into_packet();
+        assert_eq!(packet.packet_id(), 0x42);
+
+        ServerboundPacket::new(0x42, packet.data()).parse().unwrap()
+    }
+
+    #[test]
+    fn enums_round_trip() {
+        let add = Update::Action(Action::Add {
+            name: "stone".to_string(),
+            count: VarInt::new(64),
+        });
+        assert_eq!(
+            round_trip(add),
+            Update::Action(Action::Add {
+                name: "stone".to_string(),
+                count: VarInt::new(64),
+            })
+        );
+
+        let remove = Update::Action(Action::Remove(VarInt::new(3)));
+        assert_eq!(
+            round_trip(remove),
+            Update::Action(Action::Remove(VarInt::new(3)))
+        );
+
+        assert_eq!(
+            round_trip(Update::Action(Action::Clear)),
+            Update::Action(Action::Clear)
+        );
+        assert_eq!(round_trip(Update::Reset), Update::Reset);
+    }
+
+    #[test]
+    fn enums_are_written_with_their_discriminants() {
+        let mut data = BytesMut::new();
+        Action::Remove(VarInt::new(3)).write_to(&mut data);
+        assert_eq!(&data[..], &[1, 3]);
+
+        let data = Update::Action(Action::Clear).into_packet().data();
+        assert_eq!(&data[..], &[2, 5]);
+
+        let data = Update::Reset.into_packet().data();
+        assert_eq!(&data[..], &[3]);
+    }
+
+    #[test]
+    fn unknown_discriminant_is_rejected() {
+        let data = BytesMut::from(&[2u8, 9][..]);
+
+        assert!(ServerboundPacket::new(0x42, data)
+            .parse::<Update>()
+            .is_err());
+    }
+}
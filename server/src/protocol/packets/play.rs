@@ -1,4 +1,5 @@
-use crate::protocol::data_types::{DataType, Identifier, SizedDataType, VarInt};
+use crate::protocol::data_types::nbt::Nbt;
+use crate::protocol::data_types::{Chat, DataType, Identifier, Long, SizedDataType, VarInt};
 
 #[derive(Constructor, IntoPacket)]
 #[packet_id = 0x24]
@@ -6,13 +7,64 @@ pub struct JoinGame {
     entity_id: i32,
     is_hardcore: bool,
     gamemode: u8,
+    /// -1 if the player has no previous game mode.
     previous_gamemode: i8,
     world_names: Vec<Identifier>,
-    dimension_codec: Identifier,
+    /// The dimension type and biome registries.
+    dimension_codec: Nbt,
+    /// The dimension type of the world being joined.
+    dimension: Nbt,
+    world_name: Identifier,
     hashed_seed: i64,
-    max_players: u8,
-    level_type: String,
+    /// Ignored by the client.
+    max_players: VarInt,
     view_distance: VarInt,
     reduced_debug_info: bool,
     enable_respawn_screen: bool,
+    is_debug: bool,
+    is_flat: bool,
+}
+
+#[derive(Constructor, IntoPacket)]
+#[packet_id = 0x19]
+pub struct Disconnect {
+    reason: Chat,
+}
+
+/// Moves the player, which also takes the client off the loading screen after
+/// it joins.
+#[derive(Constructor, IntoPacket)]
+#[packet_id = 0x34]
+pub struct PlayerPositionAndLook {
+    x: f64,
+    y: f64,
+    z: f64,
+    yaw: f32,
+    pitch: f32,
+    /// Which of the fields are relative to the current position.
+    flags: i8,
+    /// Echoed back by the client in Teleport Confirm.
+    teleport_id: VarInt,
+}
+
+/// Sent every so often to check the client is still there, which it answers
+/// with a `KeepAliveResponse` holding the same ID.
+#[derive(Constructor, IntoPacket)]
+#[packet_id = 0x1F]
+pub struct KeepAlive {
+    id: i64,
+}
+
+// Serverbound packets, as of 1.16.3. Other versions have their IDs mapped to
+// these by `ProtocolVersion::serverbound_play_id`. The ones without a handler
+// yet are kept in `unhandled`.
+
+mod unhandled;
+
+pub use self::unhandled::*;
+
+#[derive(FromPacket)]
+#[packet_id = 0x10]
+pub struct KeepAliveResponse {
+    pub id: Long,
 }
@@ -0,0 +1,634 @@
+//! The serverbound play packets that don't have a handler yet. They're only
+//! parsed to tell them from unknown packets, so dead code is allowed here
+//! until each one gets a handler and moves out.
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+use bytes::BytesMut;
+use uuid::Uuid;
+
+use super::KeepAliveResponse;
+use crate::protocol::data_types::{
+    Byte, DataType, Double, Float, Identifier, Position, Short, Slot, UnsignedByte, VarInt, VarLong,
+};
+use crate::protocol::packets::{FromPacket, PacketId, ServerboundPacket};
+
+/// Which hand the player used.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DataType)]
+pub enum Hand {
+    MainHand,
+    OffHand,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x00]
+pub struct TeleportConfirm {
+    pub teleport_id: VarInt,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x01]
+pub struct QueryBlockNbt {
+    pub transaction_id: VarInt,
+    pub location: Position,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x02]
+pub struct SetDifficulty {
+    pub new_difficulty: UnsignedByte,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x03]
+pub struct ChatMessage {
+    #[max_len = 256]
+    pub message: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DataType)]
+pub enum ClientStatusAction {
+    PerformRespawn,
+    RequestStats,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x04]
+pub struct ClientStatus {
+    pub action: ClientStatusAction,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DataType)]
+pub enum ChatMode {
+    Enabled,
+    CommandsOnly,
+    Hidden,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DataType)]
+pub enum MainHand {
+    Left,
+    Right,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x05]
+pub struct ClientSettings {
+    #[max_len = 16]
+    pub locale: String,
+    pub view_distance: Byte,
+    pub chat_mode: ChatMode,
+    pub chat_colors: bool,
+    /// A bit mask of the skin layers shown.
+    pub displayed_skin_parts: UnsignedByte,
+    pub main_hand: MainHand,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x06]
+pub struct TabComplete {
+    pub transaction_id: VarInt,
+    #[max_len = 32500]
+    pub text: String,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x07]
+pub struct WindowConfirmation {
+    pub window_id: Byte,
+    pub action_number: Short,
+    pub accepted: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x08]
+pub struct ClickWindowButton {
+    pub window_id: Byte,
+    pub button_id: Byte,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x09]
+pub struct ClickWindow {
+    pub window_id: UnsignedByte,
+    pub slot: Short,
+    pub button: Byte,
+    pub action_number: Short,
+    pub mode: VarInt,
+    pub clicked_item: Slot,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x0A]
+pub struct CloseWindow {
+    pub window_id: UnsignedByte,
+}
+
+/// The most data a client can send in a plugin message, same as vanilla.
+pub const MAX_PLUGIN_MESSAGE_SIZE: usize = 32767;
+
+pub struct PluginMessage {
+    pub channel: Identifier,
+    /// Everything after the channel, in whatever format the channel uses.
+    pub data: BytesMut,
+}
+
+impl PacketId for PluginMessage {
+    const PACKET_ID: i32 = 0x0B;
+}
+
+impl FromPacket for PluginMessage {
+    fn from_packet(packet: ServerboundPacket) -> Result<PluginMessage> {
+        let mut data = packet.data();
+
+        let channel = Identifier::read_from(&mut data)
+            .map_err(|e| e.add_context("While reading PluginMessage::channel"))?;
+
+        if data.len() > MAX_PLUGIN_MESSAGE_SIZE {
+            return Err(anyhow!(
+                "Plugin message on {} is {} bytes, more than the max of {}",
+                channel.as_str(),
+                data.len(),
+                MAX_PLUGIN_MESSAGE_SIZE
+            ));
+        }
+
+        Ok(PluginMessage { channel, data })
+    }
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x0C]
+pub struct EditBook {
+    pub new_book: Slot,
+    pub is_signing: bool,
+    pub hand: Hand,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x0D]
+pub struct QueryEntityNbt {
+    pub transaction_id: VarInt,
+    pub entity_id: VarInt,
+}
+
+#[derive(DataType)]
+pub enum InteractAction {
+    Interact {
+        hand: Hand,
+    },
+    Attack,
+    /// Where on the entity was clicked, relative to it.
+    InteractAt {
+        target_x: Float,
+        target_y: Float,
+        target_z: Float,
+        hand: Hand,
+    },
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x0E]
+pub struct InteractEntity {
+    pub entity_id: VarInt,
+    pub action: InteractAction,
+    pub sneaking: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x0F]
+pub struct GenerateStructure {
+    pub location: Position,
+    pub levels: VarInt,
+    pub keep_jigsaws: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x11]
+pub struct LockDifficulty {
+    pub locked: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x12]
+pub struct PlayerPosition {
+    pub x: Double,
+    /// The position of the player's feet.
+    pub y: Double,
+    pub z: Double,
+    pub on_ground: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x13]
+pub struct PlayerPositionAndRotation {
+    pub x: Double,
+    pub y: Double,
+    pub z: Double,
+    pub yaw: Float,
+    pub pitch: Float,
+    pub on_ground: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x14]
+pub struct PlayerRotation {
+    pub yaw: Float,
+    pub pitch: Float,
+    pub on_ground: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x15]
+pub struct PlayerMovement {
+    pub on_ground: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x16]
+pub struct VehicleMove {
+    pub x: Double,
+    pub y: Double,
+    pub z: Double,
+    pub yaw: Float,
+    pub pitch: Float,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x17]
+pub struct SteerBoat {
+    pub left_paddle_turning: bool,
+    pub right_paddle_turning: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x18]
+pub struct PickItem {
+    pub slot_to_use: VarInt,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x19]
+pub struct CraftRecipeRequest {
+    pub window_id: Byte,
+    pub recipe: Identifier,
+    pub make_all: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x1A]
+pub struct PlayerAbilities {
+    /// 0x02 is set while flying.
+    pub flags: Byte,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DataType)]
+pub enum DiggingStatus {
+    StartedDigging,
+    CancelledDigging,
+    FinishedDigging,
+    DropItemStack,
+    DropItem,
+    /// Also used for finishing eating.
+    ShootArrow,
+    SwapItemInHand,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x1B]
+pub struct PlayerDigging {
+    pub status: DiggingStatus,
+    pub location: Position,
+    pub face: Byte,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DataType)]
+pub enum EntityActionKind {
+    StartSneaking,
+    StopSneaking,
+    LeaveBed,
+    StartSprinting,
+    StopSprinting,
+    StartJumpWithHorse,
+    StopJumpWithHorse,
+    OpenHorseInventory,
+    StartFlyingWithElytra,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x1C]
+pub struct EntityAction {
+    pub entity_id: VarInt,
+    pub action: EntityActionKind,
+    /// How hard a horse is jumping, from 0 to 100.
+    pub jump_boost: VarInt,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x1D]
+pub struct SteerVehicle {
+    pub sideways: Float,
+    pub forward: Float,
+    /// 0x01 to jump, 0x02 to dismount.
+    pub flags: UnsignedByte,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x1E]
+pub enum RecipeBookData {
+    DisplayedRecipe {
+        recipe_id: Identifier,
+    },
+    RecipeBookStates {
+        crafting_book_open: bool,
+        crafting_filter_active: bool,
+        smelting_book_open: bool,
+        smelting_filter_active: bool,
+        blasting_book_open: bool,
+        blasting_filter_active: bool,
+        smoking_book_open: bool,
+        smoking_filter_active: bool,
+    },
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x1F]
+pub struct NameItem {
+    #[max_len = 32767]
+    pub item_name: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DataType)]
+pub enum ResourcePackResult {
+    SuccessfullyLoaded,
+    Declined,
+    FailedDownload,
+    Accepted,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x20]
+pub struct ResourcePackStatus {
+    pub result: ResourcePackResult,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x21]
+pub enum AdvancementTab {
+    OpenedTab { tab_id: Identifier },
+    ClosedScreen,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x22]
+pub struct SelectTrade {
+    pub selected_slot: VarInt,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x23]
+pub struct SetBeaconEffect {
+    pub primary_effect: VarInt,
+    pub secondary_effect: VarInt,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x24]
+pub struct HeldItemChange {
+    /// The hotbar slot, from 0 to 8.
+    pub slot: Short,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DataType)]
+pub enum CommandBlockMode {
+    Sequence,
+    Auto,
+    Redstone,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x25]
+pub struct UpdateCommandBlock {
+    pub location: Position,
+    #[max_len = 32767]
+    pub command: String,
+    pub mode: CommandBlockMode,
+    /// 0x01 to track output, 0x02 if conditional, 0x04 if always active.
+    pub flags: Byte,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x26]
+pub struct UpdateCommandBlockMinecart {
+    pub entity_id: VarInt,
+    #[max_len = 32767]
+    pub command: String,
+    pub track_output: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x27]
+pub struct CreativeInventoryAction {
+    pub slot: Short,
+    pub clicked_item: Slot,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x28]
+pub struct UpdateJigsawBlock {
+    pub location: Position,
+    pub name: Identifier,
+    pub target: Identifier,
+    pub pool: Identifier,
+    #[max_len = 32767]
+    pub final_state: String,
+    #[max_len = 32767]
+    pub joint_type: String,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x29]
+pub struct UpdateStructureBlock {
+    pub location: Position,
+    pub action: VarInt,
+    pub mode: VarInt,
+    #[max_len = 32767]
+    pub name: String,
+    pub offset_x: Byte,
+    pub offset_y: Byte,
+    pub offset_z: Byte,
+    pub size_x: Byte,
+    pub size_y: Byte,
+    pub size_z: Byte,
+    pub mirror: VarInt,
+    pub rotation: VarInt,
+    #[max_len = 32767]
+    pub metadata: String,
+    pub integrity: Float,
+    pub seed: VarLong,
+    pub flags: Byte,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x2A]
+pub struct UpdateSign {
+    pub location: Position,
+    #[max_len = 384]
+    pub line_1: String,
+    #[max_len = 384]
+    pub line_2: String,
+    #[max_len = 384]
+    pub line_3: String,
+    #[max_len = 384]
+    pub line_4: String,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x2B]
+pub struct Animation {
+    pub hand: Hand,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x2C]
+pub struct Spectate {
+    pub target_player: Uuid,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x2D]
+pub struct PlayerBlockPlacement {
+    pub hand: Hand,
+    pub location: Position,
+    pub face: VarInt,
+    pub cursor_position_x: Float,
+    pub cursor_position_y: Float,
+    pub cursor_position_z: Float,
+    pub inside_block: bool,
+}
+
+#[derive(FromPacket)]
+#[packet_id = 0x2E]
+pub struct UseItem {
+    pub hand: Hand,
+}
+
+serverbound_packets! {
+    /// The packets in the play state. Only keep alives are handled so far, the
+    /// rest are kept for when they have handlers.
+        pub enum Serverbound {
+        TeleportConfirm(TeleportConfirm),
+        QueryBlockNbt(QueryBlockNbt),
+        SetDifficulty(SetDifficulty),
+        ChatMessage(ChatMessage),
+        ClientStatus(ClientStatus),
+        ClientSettings(ClientSettings),
+        TabComplete(TabComplete),
+        WindowConfirmation(WindowConfirmation),
+        ClickWindowButton(ClickWindowButton),
+        ClickWindow(ClickWindow),
+        CloseWindow(CloseWindow),
+        PluginMessage(PluginMessage),
+        EditBook(EditBook),
+        QueryEntityNbt(QueryEntityNbt),
+        InteractEntity(InteractEntity),
+        GenerateStructure(GenerateStructure),
+        KeepAlive(KeepAliveResponse),
+        LockDifficulty(LockDifficulty),
+        PlayerPosition(PlayerPosition),
+        PlayerPositionAndRotation(PlayerPositionAndRotation),
+        PlayerRotation(PlayerRotation),
+        PlayerMovement(PlayerMovement),
+        VehicleMove(VehicleMove),
+        SteerBoat(SteerBoat),
+        PickItem(PickItem),
+        CraftRecipeRequest(CraftRecipeRequest),
+        PlayerAbilities(PlayerAbilities),
+        PlayerDigging(PlayerDigging),
+        EntityAction(EntityAction),
+        SteerVehicle(SteerVehicle),
+        RecipeBookData(RecipeBookData),
+        NameItem(NameItem),
+        ResourcePackStatus(ResourcePackStatus),
+        AdvancementTab(AdvancementTab),
+        SelectTrade(SelectTrade),
+        SetBeaconEffect(SetBeaconEffect),
+        HeldItemChange(HeldItemChange),
+        UpdateCommandBlock(UpdateCommandBlock),
+        UpdateCommandBlockMinecart(UpdateCommandBlockMinecart),
+        CreativeInventoryAction(CreativeInventoryAction),
+        UpdateJigsawBlock(UpdateJigsawBlock),
+        UpdateStructureBlock(UpdateStructureBlock),
+        UpdateSign(UpdateSign),
+        Animation(Animation),
+        Spectate(Spectate),
+        PlayerBlockPlacement(PlayerBlockPlacement),
+        UseItem(UseItem),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::data_types::SizedDataType;
+
+    #[test]
+    fn every_packet_is_listed() {
+        let mut ids = Serverbound::IDS.to_vec();
+        ids.sort_unstable();
+
+        assert_eq!(ids, (0x00..=0x2E).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn packets_parse_by_id() {
+        let mut data = BytesMut::new();
+        VarInt::new(0x2A).write_to(&mut data);
+        InteractAction::InteractAt {
+            target_x: 0.5,
+            target_y: 1.0,
+            target_z: 0.25,
+            hand: Hand::OffHand,
+        }
+        .write_to(&mut data);
+        true.write_to(&mut data);
+
+        match Serverbound::parse(ServerboundPacket::new(0x0E, data)).unwrap() {
+            Some(Serverbound::InteractEntity(InteractEntity {
+                entity_id,
+                action: InteractAction::InteractAt { hand, .. },
+                sneaking: true,
+            })) => {
+                assert_eq!(entity_id.value(), 0x2A);
+                assert_eq!(hand, Hand::OffHand);
+            }
+            _ => panic!("parsed the wrong packet"),
+        }
+
+        let mut data = BytesMut::new();
+        "Hello".to_string().write_to(&mut data);
+        match Serverbound::parse(ServerboundPacket::new(0x03, data)).unwrap() {
+            Some(Serverbound::ChatMessage(chat)) => assert_eq!(chat.message, "Hello"),
+            _ => panic!("parsed the wrong packet"),
+        }
+
+        let unknown = ServerboundPacket::new(0x2F, BytesMut::new());
+        assert!(Serverbound::parse(unknown).unwrap().is_none());
+    }
+
+    #[test]
+    fn plugin_messages_keep_their_data() {
+        let mut data = BytesMut::new();
+        Identifier::new("minecraft:brand".to_string()).write_to(&mut data);
+        "vanilla".to_string().write_to(&mut data);
+
+        match Serverbound::parse(ServerboundPacket::new(0x0B, data)).unwrap() {
+            Some(Serverbound::PluginMessage(message)) => {
+                assert_eq!(message.channel.as_str(), "minecraft:brand");
+                assert_eq!(&message.data[..], b"\x07vanilla");
+            }
+            _ => panic!("parsed the wrong packet"),
+        }
+    }
+}
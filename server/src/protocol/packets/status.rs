@@ -1,82 +1,116 @@
-use anyhow::{anyhow, Error};
-use bytes::{Buf, BytesMut};
-use serde_json::json;
+use bytes::BytesMut;
+use serde_json::{json, Value};
+use uuid::Uuid;
 
 use crate::protocol::data_types::{DataType, Long, SizedDataType};
-use crate::protocol::packets::{ClientboundPacket, FromPacket, IntoPacket, ServerboundPacket};
+use crate::protocol::packets::{ClientboundPacket, IntoPacket};
+use crate::protocol::version::ProtocolVersion;
 
+#[derive(FromPacket)]
+#[packet_id = 0x00]
 pub struct Request;
 
-impl FromPacket for Request {
-    fn from_packet(packet: ServerboundPacket) -> Result<Self, Error> {
-        // TODO make sure we are at the end of the packet here...
-        // The request packet has no payload
-        if packet.data().remaining() == 0 {
-            Ok(Request)
-        } else {
-            Err(anyhow!("Bytes remaining in request packet"))
-        }
-    }
-}
+/// Prefixed to the game version in the version name shown in the server list
+/// when the client is incompatible.
+pub const SERVER_NAME: &str = "MC Server";
 
 pub struct Response {
-    response: String,
+    version: ProtocolVersion,
+    players_max: usize,
+    players_online: usize,
+    sample: Vec<(String, Uuid)>,
+    description: Value,
+    favicon: Option<String>,
 }
 
 impl Response {
+    /// Builds a status response. `sample` is the list of players (name and
+    /// UUID) shown when hovering over the player count, `description` is a
+    /// JSON chat component and `favicon` is a `data:image/png;base64,` URI.
+    /// `version` is the protocol version advertised to the client.
     pub fn new(
+        version: ProtocolVersion,
         players_max: usize,
         players_online: usize,
-        motd: String,
+        sample: Vec<(String, Uuid)>,
+        description: Value,
         favicon: Option<String>,
     ) -> Response {
-        let favicon = favicon.unwrap_or_else(|| "".to_string());
+        Response {
+            version,
+            players_max,
+            players_online,
+            sample,
+            description,
+            favicon,
+        }
+    }
 
-        // TODO dynamic protocol number and version name
-        let response = json!({
-            "version": {
-                "name": "MC Server 1.16.3",
-                "protocol": 753,
-            },
-            "players": {
-                "max": players_max,
-                "online": players_online,
-            },
-            "description": {
-                "text": motd,
-            },
-            "favicon": favicon
-        })
-        .to_string();
+    pub fn version_name(&self) -> String {
+        format!("{} {}", SERVER_NAME, self.version.name())
+    }
+
+    pub fn players_max(&self) -> usize {
+        self.players_max
+    }
+
+    pub fn players_online(&self) -> usize {
+        self.players_online
+    }
 
-        Response { response }
+    pub fn description(&self) -> &Value {
+        &self.description
     }
 }
 
 impl IntoPacket for Response {
     fn into_packet(self) -> ClientboundPacket {
+        let version_name = self.version_name();
+
+        let sample: Vec<Value> = self
+            .sample
+            .into_iter()
+            .map(|(name, id)| {
+                json!({
+                    "name": name,
+                    "id": id.to_hyphenated().to_string(),
+                })
+            })
+            .collect();
+
+        let mut response = json!({
+            "version": {
+                "name": version_name,
+                "protocol": self.version.id(),
+            },
+            "players": {
+                "max": self.players_max,
+                "online": self.players_online,
+                "sample": sample,
+            },
+            "description": self.description,
+        });
+
+        if let Some(favicon) = self.favicon {
+            response["favicon"] = Value::String(favicon);
+        }
+
+        let response = response.to_string();
+
         // TODO Is there a better way to make a temporary buffer?
-        let mut data = BytesMut::with_capacity(self.response.size());
-        self.response.write_to(&mut data);
+        let mut data = BytesMut::with_capacity(response.size());
+        response.write_to(&mut data);
 
         ClientboundPacket::new(0x00, data)
     }
 }
 
+#[derive(FromPacket)]
+#[packet_id = 0x01]
 pub struct Ping {
     payload: Long,
 }
 
-impl FromPacket for Ping {
-    fn from_packet(packet: ServerboundPacket) -> Result<Ping, Error> {
-        let mut data = packet.data();
-
-        Ok(Ping {
-            payload: Long::read_from(&mut data)?,
-        })
-    }
-}
-
 pub struct Pong {
     payload: Long,
 }
@@ -97,3 +131,10 @@ impl IntoPacket for Pong {
         ClientboundPacket::new(0x01, data)
     }
 }
+
+serverbound_packets! {
+    pub enum Serverbound {
+        Request(Request),
+        Ping(Ping),
+    }
+}
@@ -0,0 +1,348 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The first 12 bytes of a version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The longest version 1 header, including the CRLF.
+const MAX_V1_HEADER_SIZE: usize = 107;
+
+/// A range of addresses, like `10.0.0.0/8`, that load balancers are trusted to
+/// connect from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn new(address: IpAddr, prefix_len: u8) -> Result<Cidr> {
+        let max = max_prefix_len(address);
+        if prefix_len > max {
+            return Err(anyhow!(
+                "Prefix length {} is longer than {} bits",
+                prefix_len,
+                max
+            ));
+        }
+
+        Ok(Cidr {
+            address,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, canonical_ip(address)) {
+            (IpAddr::V4(range), IpAddr::V4(address)) => {
+                prefix_matches(&range.octets(), &address.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(range), IpAddr::V6(address)) => {
+                prefix_matches(&range.octets(), &address.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    /// Parses a range in CIDR notation. A lone address is a range of just
+    /// that address.
+    fn from_str(s: &str) -> Result<Cidr> {
+        let (address, prefix_len) = match s.find('/') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| anyhow!("Bad address {:?}", address))?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .map_err(|_| anyhow!("Bad prefix length {:?}", prefix_len))?,
+            None => max_prefix_len(address),
+        };
+
+        Cidr::new(address, prefix_len)
+    }
+}
+
+/// The address a client is known by. Dual stack sockets report IPv4 clients as
+/// mapped IPv6 addresses, like `::ffff:127.0.0.1`, which are turned back into
+/// the IPv4 address so they match bans, limits and trusted ranges.
+pub fn canonical_ip(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => match v6.octets() {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, a, b, c, d] => {
+                IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+            }
+            _ => address,
+        },
+        IpAddr::V4(_) => address,
+    }
+}
+
+fn max_prefix_len(address: IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Whether the first `prefix_len` bits of the two addresses are the same.
+fn prefix_matches(range: &[u8], address: &[u8], prefix_len: u8) -> bool {
+    let whole_bytes = prefix_len as usize / 8;
+    if range[..whole_bytes] != address[..whole_bytes] {
+        return false;
+    }
+
+    let remaining_bits = prefix_len % 8;
+    if remaining_bits == 0 {
+        return true;
+    }
+
+    let mask = 0xFFu8 << (8 - remaining_bits);
+    range[whole_bytes] & mask == address[whole_bytes] & mask
+}
+
+/// Reads the PROXY protocol header a load balancer sends before anything
+/// else on a connection, in either version. Exactly the header is read, so
+/// the rest of the connection is left for the codec.
+///
+/// Returns the address of the client the connection is for, or None if the
+/// balancer didn't say, as it does for its own health checks.
+pub async fn read_header<R: AsyncRead + Unpin>(src: &mut R) -> Result<Option<SocketAddr>> {
+    // The shortest version 1 header is "PROXY UNKNOWN\r\n", so this never
+    // reads past the end of either kind.
+    let mut start = [0u8; 12];
+    src.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        src.read_exact(&mut fixed).await?;
+
+        let len = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut addresses = vec![0u8; len];
+        src.read_exact(&mut addresses).await?;
+
+        parse_v2(fixed[0], fixed[1], &addresses)
+    } else if start.starts_with(b"PROXY ") {
+        // Read a byte at a time so nothing after the header is consumed
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= MAX_V1_HEADER_SIZE {
+                return Err(anyhow!("PROXY header is too long"));
+            }
+
+            line.push(src.read_u8().await?);
+        }
+
+        parse_v1(&line)
+    } else {
+        Err(anyhow!("Connection did not start with a PROXY header"))
+    }
+}
+
+/// Parses a version 1 header, like `PROXY TCP4 192.0.2.1 198.51.100.1 56324
+/// 25565\r\n`.
+fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line)
+        .map_err(|_| anyhow!("PROXY header is not ASCII"))?
+        .trim_end_matches("\r\n");
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    let (source, source_port) = match parts[..] {
+        ["PROXY", "UNKNOWN", ..] => return Ok(None),
+        ["PROXY", "TCP4", source, _, source_port, _] => {
+            let source: Ipv4Addr = source
+                .parse()
+                .map_err(|_| anyhow!("Bad source address {:?}", source))?;
+            (IpAddr::V4(source), source_port)
+        }
+        ["PROXY", "TCP6", source, _, source_port, _] => {
+            let source: Ipv6Addr = source
+                .parse()
+                .map_err(|_| anyhow!("Bad source address {:?}", source))?;
+            (IpAddr::V6(source), source_port)
+        }
+        _ => return Err(anyhow!("Bad PROXY header {:?}", line)),
+    };
+
+    let source_port = source_port
+        .parse()
+        .map_err(|_| anyhow!("Bad source port {:?}", source_port))?;
+
+    Ok(Some(SocketAddr::new(source, source_port)))
+}
+
+/// Parses the rest of a version 2 header, from the version and command byte,
+/// the address family and protocol byte, and the address block. Anything in
+/// the block after the addresses is an extension and is skipped.
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(anyhow!(
+            "Unsupported PROXY version {}",
+            version_command >> 4
+        ));
+    }
+
+    match version_command & 0x0F {
+        // LOCAL, the balancer connecting on its own behalf
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        command => return Err(anyhow!("Unknown PROXY command {:#x}", command)),
+    }
+
+    let (source, port_offset) = match family {
+        // TCP over IPv4
+        0x11 if addresses.len() >= 12 => {
+            let mut source = [0u8; 4];
+            source.copy_from_slice(&addresses[..4]);
+            (IpAddr::from(source), 8)
+        }
+        // TCP over IPv6
+        0x21 if addresses.len() >= 36 => {
+            let mut source = [0u8; 16];
+            source.copy_from_slice(&addresses[..16]);
+            (IpAddr::from(source), 32)
+        }
+        0x11 | 0x21 => return Err(anyhow!("PROXY header addresses are too short")),
+        // Other families and protocols can't be for a Minecraft client, so
+        // the connection's own address is kept, as the spec recommends.
+        _ => return Ok(None),
+    };
+
+    let source_port = u16::from_be_bytes([addresses[port_offset], addresses[port_offset + 1]]);
+
+    Ok(Some(SocketAddr::new(source, source_port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(header: &[u8]) -> Result<Option<SocketAddr>> {
+        // The handshake after the header has to be left alone
+        let mut data = header.to_vec();
+        data.extend_from_slice(b"\x10\x00");
+
+        let mut src = &data[..];
+        let address = read_header(&mut src).await?;
+        assert_eq!(src, b"\x10\x00");
+        Ok(address)
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn reads_v1_headers() {
+        assert_eq!(
+            read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 25565\r\n")
+                .await
+                .unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert_eq!(
+            read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 25565\r\n")
+                .await
+                .unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+
+        assert!(read(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n")
+            .await
+            .is_err());
+        assert!(read(b"PROXY TCP4 192.0.2.1 198.51.100.1 99999 25565\r\n")
+            .await
+            .is_err());
+        assert!(read(&[b"PROXY ".to_vec(), vec![b'A'; 200]].concat())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_v2_headers() {
+        let mut v4 = vec![192, 0, 2, 1, 198, 51, 100, 1];
+        v4.extend_from_slice(&56324u16.to_be_bytes());
+        v4.extend_from_slice(&25565u16.to_be_bytes());
+        assert_eq!(
+            read(&v2(0x1, 0x11, &v4)).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+
+        // With an extension after the addresses
+        let mut v6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        v6.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        v6.extend_from_slice(&56324u16.to_be_bytes());
+        v6.extend_from_slice(&25565u16.to_be_bytes());
+        v6.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            read(&v2(0x1, 0x21, &v6)).await.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+
+        assert_eq!(read(&v2(0x0, 0x00, &[])).await.unwrap(), None);
+        assert!(read(&v2(0x1, 0x11, &v4[..6])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn requires_a_header() {
+        assert!(read(b"\x10\x00\xf2\x05\x09localhost\x63\xdd\x02")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn mapped_addresses_are_made_ipv4() {
+        let mapped: IpAddr = "::ffff:10.1.2.3".parse().unwrap();
+        let v6: IpAddr = "::1".parse().unwrap();
+
+        assert_eq!(canonical_ip(mapped), "10.1.2.3".parse::<IpAddr>().unwrap());
+        assert_eq!(canonical_ip(v6), v6);
+    }
+
+    #[test]
+    fn cidrs_contain_their_addresses() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains("10.1.2.3".parse().unwrap()));
+        assert!(private.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!private.contains("11.0.0.0".parse().unwrap()));
+
+        let odd: Cidr = "192.0.2.128/25".parse().unwrap();
+        assert!(odd.contains("192.0.2.200".parse().unwrap()));
+        assert!(!odd.contains("192.0.2.100".parse().unwrap()));
+
+        let single: Cidr = "::1".parse().unwrap();
+        assert_eq!(single.to_string(), "::1/128");
+        assert!(single.contains("::1".parse().unwrap()));
+        assert!(!single.contains("127.0.0.1".parse().unwrap()));
+
+        let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains("203.0.113.7".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+
+/// The protocol versions the server can speak.
+///
+/// Packets are defined (and their `#[packet_id]`s given) in terms of the
+/// native version. Play state packet IDs for every other version are mapped
+/// to and from the native ones here. Handshake, status and login packet IDs
+/// are the same for every supported version.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    /// 1.16.3, protocol 753.
+    V1_16_3,
+    /// 1.16.4 and 1.16.5, protocol 754.
+    V1_16_4,
+}
+
+impl ProtocolVersion {
+    /// The version packets are defined in terms of.
+    pub const NATIVE: ProtocolVersion = ProtocolVersion::V1_16_3;
+
+    /// The newest supported version, which is what gets advertised to clients
+    /// that don't speak any supported version.
+    pub const LATEST: ProtocolVersion = ProtocolVersion::V1_16_4;
+
+    pub fn from_id(id: i32) -> Option<ProtocolVersion> {
+        match id {
+            753 => Some(ProtocolVersion::V1_16_3),
+            754 => Some(ProtocolVersion::V1_16_4),
+            _ => None,
+        }
+    }
+
+    /// The protocol version number sent in the handshake.
+    pub fn id(self) -> i32 {
+        match self {
+            ProtocolVersion::V1_16_3 => 753,
+            ProtocolVersion::V1_16_4 => 754,
+        }
+    }
+
+    /// The name of the game version(s) using this protocol.
+    pub fn name(self) -> &'static str {
+        match self {
+            ProtocolVersion::V1_16_3 => "1.16.3",
+            ProtocolVersion::V1_16_4 => "1.16.5",
+        }
+    }
+
+    /// Maps the ID of a clientbound play packet from the native version to
+    /// this version.
+    pub fn clientbound_play_id(self, native_id: i32) -> i32 {
+        match self {
+            // No clientbound packets changed between 1.16.3 and 1.16.4
+            ProtocolVersion::V1_16_3 | ProtocolVersion::V1_16_4 => native_id,
+        }
+    }
+
+    /// Maps the ID of a serverbound play packet in this version to the native
+    /// version. Returns None for packets with no native equivalent.
+    pub fn serverbound_play_id(self, id: i32) -> Option<i32> {
+        match self {
+            ProtocolVersion::V1_16_3 => Some(id),
+            ProtocolVersion::V1_16_4 => match id {
+                0x00..=0x1D => Some(id),
+                // 1.16.4 split Recipe Book Data (0x1E) into Set Recipe Book
+                // State and Set Displayed Recipe, which don't map onto it
+                // without rewriting the payload.
+                0x1E | 0x1F => None,
+                0x20..=0x2F => Some(id - 1),
+                // Not a packet in either version, left for the caller to
+                // reject
+                _ => Some(id),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_ids_round_trip() {
+        for version in &[ProtocolVersion::V1_16_3, ProtocolVersion::V1_16_4] {
+            assert_eq!(ProtocolVersion::from_id(version.id()), Some(*version));
+        }
+
+        assert_eq!(ProtocolVersion::from_id(340), None);
+    }
+
+    #[test]
+    fn serverbound_play_ids_map_to_native() {
+        let version = ProtocolVersion::V1_16_4;
+
+        // Keep Alive
+        assert_eq!(version.serverbound_play_id(0x10), Some(0x10));
+        // Set Displayed Recipe
+        assert_eq!(version.serverbound_play_id(0x1F), None);
+        // Name Item
+        assert_eq!(version.serverbound_play_id(0x20), Some(0x1F));
+        // Use Item
+        assert_eq!(version.serverbound_play_id(0x2F), Some(0x2E));
+    }
+}
@@ -0,0 +1,447 @@
+//! The registries the client is sent in the dimension codec when it joins.
+//!
+//! Since 1.16.2 the client doesn't know any dimension types or biomes of its
+//! own, so everything it needs to render the world has to be sent to it. The
+//! entries here are the vanilla ones, with the same names and IDs, so chunk
+//! data can refer to biomes by their vanilla IDs.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::protocol::data_types::nbt::ser::to_compound;
+use crate::protocol::data_types::nbt::{Compound, Nbt};
+
+pub const DIMENSION_TYPE_REGISTRY: &str = "minecraft:dimension_type";
+pub const BIOME_REGISTRY: &str = "minecraft:worldgen/biome";
+
+pub const OVERWORLD: &str = "minecraft:overworld";
+pub const THE_NETHER: &str = "minecraft:the_nether";
+pub const THE_END: &str = "minecraft:the_end";
+
+/// An entry in a registry, `id` is what the protocol refers to it by.
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry<T: 'static> {
+    pub name: &'static str,
+    pub id: i32,
+    pub element: T,
+}
+
+#[derive(Serialize)]
+struct Registry<T: 'static> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    value: &'static [Entry<T>],
+}
+
+/// The properties of a dimension, which decide how the client lights and
+/// renders it.
+#[derive(Clone, Debug, Serialize)]
+pub struct DimensionType {
+    pub piglin_safe: bool,
+    pub natural: bool,
+    pub ambient_light: f32,
+    /// The time of day is stuck at this many ticks, if set.
+    pub fixed_time: Option<i64>,
+    pub infiniburn: &'static str,
+    pub respawn_anchor_works: bool,
+    pub has_skylight: bool,
+    pub bed_works: bool,
+    pub effects: &'static str,
+    pub has_raids: bool,
+    pub logical_height: i32,
+    pub coordinate_scale: f64,
+    pub ultrawarm: bool,
+    pub has_ceiling: bool,
+}
+
+impl DimensionType {
+    /// The dimension type as sent in its own right in Join Game and Respawn.
+    pub fn to_nbt(&self) -> Result<Nbt> {
+        Ok(Nbt::new(to_compound(self)?))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precipitation {
+    None,
+    Rain,
+    Snow,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Biome {
+    pub precipitation: Precipitation,
+    pub depth: f32,
+    pub temperature: f32,
+    pub scale: f32,
+    pub downfall: f32,
+    pub category: &'static str,
+    pub effects: BiomeEffects,
+}
+
+/// How the client draws a biome and the sounds it plays there. Colors are
+/// packed RGB.
+#[derive(Clone, Debug, Serialize)]
+pub struct BiomeEffects {
+    pub sky_color: i32,
+    pub fog_color: i32,
+    pub water_color: i32,
+    pub water_fog_color: i32,
+    pub ambient_sound: Option<&'static str>,
+    pub mood_sound: Option<MoodSound>,
+    pub additions_sound: Option<AdditionsSound>,
+    pub music: Option<Music>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MoodSound {
+    pub sound: &'static str,
+    pub tick_delay: i32,
+    pub block_search_extent: i32,
+    pub offset: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AdditionsSound {
+    pub sound: &'static str,
+    pub tick_chance: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Music {
+    pub sound: &'static str,
+    pub min_delay: i32,
+    pub max_delay: i32,
+    pub replace_current_music: bool,
+}
+
+/// The cave sounds played in most biomes.
+const CAVE_MOOD: MoodSound = MoodSound {
+    sound: "minecraft:ambient.cave",
+    tick_delay: 6000,
+    block_search_extent: 8,
+    offset: 2.0,
+};
+
+const WATER_COLOR: i32 = 4159204;
+const WATER_FOG_COLOR: i32 = 329011;
+
+/// Vanilla's `minecraft:overworld_caves` (ID 1) is left out, nothing uses it.
+pub const DIMENSION_TYPES: &[Entry<DimensionType>] = &[
+    Entry {
+        name: OVERWORLD,
+        id: 0,
+        element: DimensionType {
+            piglin_safe: false,
+            natural: true,
+            ambient_light: 0.0,
+            fixed_time: None,
+            infiniburn: "minecraft:infiniburn_overworld",
+            respawn_anchor_works: false,
+            has_skylight: true,
+            bed_works: true,
+            effects: "minecraft:overworld",
+            has_raids: true,
+            logical_height: 256,
+            coordinate_scale: 1.0,
+            ultrawarm: false,
+            has_ceiling: false,
+        },
+    },
+    Entry {
+        name: THE_NETHER,
+        id: 2,
+        element: DimensionType {
+            piglin_safe: true,
+            natural: false,
+            ambient_light: 0.1,
+            fixed_time: Some(18000),
+            infiniburn: "minecraft:infiniburn_nether",
+            respawn_anchor_works: true,
+            has_skylight: false,
+            bed_works: false,
+            effects: "minecraft:the_nether",
+            has_raids: false,
+            logical_height: 128,
+            coordinate_scale: 8.0,
+            ultrawarm: true,
+            has_ceiling: true,
+        },
+    },
+    Entry {
+        name: THE_END,
+        id: 3,
+        element: DimensionType {
+            piglin_safe: false,
+            natural: false,
+            ambient_light: 0.0,
+            fixed_time: Some(6000),
+            infiniburn: "minecraft:infiniburn_end",
+            respawn_anchor_works: false,
+            has_skylight: false,
+            bed_works: false,
+            effects: "minecraft:the_end",
+            has_raids: true,
+            logical_height: 256,
+            coordinate_scale: 1.0,
+            ultrawarm: false,
+            has_ceiling: false,
+        },
+    },
+];
+
+/// The client falls back to plains for any biome it doesn't know, so that one
+/// has to be here.
+pub const BIOMES: &[Entry<Biome>] = &[
+    Entry {
+        name: "minecraft:ocean",
+        id: 0,
+        element: Biome {
+            precipitation: Precipitation::Rain,
+            depth: -1.0,
+            temperature: 0.5,
+            scale: 0.1,
+            downfall: 0.5,
+            category: "ocean",
+            effects: BiomeEffects {
+                sky_color: 8103167,
+                fog_color: 12638463,
+                water_color: WATER_COLOR,
+                water_fog_color: WATER_FOG_COLOR,
+                ambient_sound: None,
+                mood_sound: Some(CAVE_MOOD),
+                additions_sound: None,
+                music: None,
+            },
+        },
+    },
+    Entry {
+        name: "minecraft:plains",
+        id: 1,
+        element: Biome {
+            precipitation: Precipitation::Rain,
+            depth: 0.125,
+            temperature: 0.8,
+            scale: 0.05,
+            downfall: 0.4,
+            category: "plains",
+            effects: BiomeEffects {
+                sky_color: 7907327,
+                fog_color: 12638463,
+                water_color: WATER_COLOR,
+                water_fog_color: WATER_FOG_COLOR,
+                ambient_sound: None,
+                mood_sound: Some(CAVE_MOOD),
+                additions_sound: None,
+                music: None,
+            },
+        },
+    },
+    Entry {
+        name: "minecraft:nether_wastes",
+        id: 8,
+        element: Biome {
+            precipitation: Precipitation::None,
+            depth: 0.1,
+            temperature: 2.0,
+            scale: 0.2,
+            downfall: 0.0,
+            category: "nether",
+            effects: BiomeEffects {
+                sky_color: 7254527,
+                fog_color: 3344392,
+                water_color: WATER_COLOR,
+                water_fog_color: WATER_FOG_COLOR,
+                ambient_sound: Some("minecraft:ambient.nether_wastes.loop"),
+                mood_sound: Some(MoodSound {
+                    sound: "minecraft:ambient.nether_wastes.mood",
+                    ..CAVE_MOOD
+                }),
+                additions_sound: Some(AdditionsSound {
+                    sound: "minecraft:ambient.nether_wastes.additions",
+                    tick_chance: 0.0111,
+                }),
+                music: Some(Music {
+                    sound: "minecraft:music.nether.nether_wastes",
+                    min_delay: 12000,
+                    max_delay: 24000,
+                    replace_current_music: false,
+                }),
+            },
+        },
+    },
+    Entry {
+        name: "minecraft:the_end",
+        id: 9,
+        element: Biome {
+            precipitation: Precipitation::None,
+            depth: 0.1,
+            temperature: 0.5,
+            scale: 0.2,
+            downfall: 0.5,
+            category: "the_end",
+            effects: BiomeEffects {
+                sky_color: 0,
+                fog_color: 10518688,
+                water_color: WATER_COLOR,
+                water_fog_color: WATER_FOG_COLOR,
+                ambient_sound: None,
+                mood_sound: Some(CAVE_MOOD),
+                additions_sound: None,
+                music: None,
+            },
+        },
+    },
+    Entry {
+        name: "minecraft:snowy_tundra",
+        id: 12,
+        element: Biome {
+            precipitation: Precipitation::Snow,
+            depth: 0.125,
+            temperature: 0.0,
+            scale: 0.05,
+            downfall: 0.5,
+            category: "icy",
+            effects: BiomeEffects {
+                sky_color: 8364543,
+                fog_color: 12638463,
+                water_color: WATER_COLOR,
+                water_fog_color: WATER_FOG_COLOR,
+                ambient_sound: None,
+                mood_sound: Some(CAVE_MOOD),
+                additions_sound: None,
+                music: None,
+            },
+        },
+    },
+    Entry {
+        name: "minecraft:the_void",
+        id: 127,
+        element: Biome {
+            precipitation: Precipitation::None,
+            depth: 0.1,
+            temperature: 0.5,
+            scale: 0.2,
+            downfall: 0.5,
+            category: "none",
+            effects: BiomeEffects {
+                sky_color: 8103167,
+                fog_color: 12638463,
+                water_color: WATER_COLOR,
+                water_fog_color: WATER_FOG_COLOR,
+                ambient_sound: None,
+                mood_sound: Some(CAVE_MOOD),
+                additions_sound: None,
+                music: None,
+            },
+        },
+    },
+];
+
+/// Looks up a dimension type by name.
+pub fn dimension_type(name: &str) -> Result<&'static DimensionType> {
+    DIMENSION_TYPES
+        .iter()
+        .find(|entry| entry.name == name)
+        .map(|entry| &entry.element)
+        .ok_or_else(|| anyhow!("Unknown dimension type {}", name))
+}
+
+/// Builds the dimension codec sent in Join Game, holding the dimension type
+/// and biome registries.
+pub fn dimension_codec() -> Result<Nbt> {
+    #[derive(Serialize)]
+    struct Codec {
+        #[serde(rename = "minecraft:dimension_type")]
+        dimension_types: Registry<DimensionType>,
+        #[serde(rename = "minecraft:worldgen/biome")]
+        biomes: Registry<Biome>,
+    }
+
+    let codec: Compound = to_compound(&Codec {
+        dimension_types: Registry {
+            kind: DIMENSION_TYPE_REGISTRY,
+            value: DIMENSION_TYPES,
+        },
+        biomes: Registry {
+            kind: BIOME_REGISTRY,
+            value: BIOMES,
+        },
+    })?;
+
+    Ok(Nbt::new(codec))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::protocol::data_types::nbt::Tag;
+    use crate::protocol::data_types::DataType;
+
+    fn entries<'a>(codec: &'a Compound, registry: &str) -> &'a [Tag] {
+        match codec.get(registry) {
+            Some(Tag::Compound(registry)) => match registry.get("value") {
+                Some(Tag::List(entries)) => entries,
+                other => panic!("bad registry entries {:?}", other),
+            },
+            other => panic!("missing registry {:?}", other),
+        }
+    }
+
+    #[test]
+    fn codec_holds_both_registries() {
+        let codec = dimension_codec().unwrap();
+
+        let plains = entries(codec.root(), BIOME_REGISTRY)
+            .iter()
+            .find(|entry| match entry {
+                Tag::Compound(entry) => entry.get("name") == Some(&Tag::from("minecraft:plains")),
+                _ => false,
+            });
+        match plains {
+            Some(Tag::Compound(plains)) => assert_eq!(plains.get("id"), Some(&Tag::Int(1))),
+            other => panic!("plains missing from {:?}", other),
+        }
+
+        assert_eq!(
+            entries(codec.root(), DIMENSION_TYPE_REGISTRY).len(),
+            DIMENSION_TYPES.len()
+        );
+    }
+
+    #[test]
+    fn dimension_types_use_nbt_types() {
+        let nether = dimension_type(THE_NETHER).unwrap().to_nbt().unwrap();
+
+        assert_eq!(nether.root().get("piglin_safe"), Some(&Tag::Byte(1)));
+        assert_eq!(nether.root().get("fixed_time"), Some(&Tag::Long(18000)));
+        assert_eq!(
+            nether.root().get("coordinate_scale"),
+            Some(&Tag::Double(8.0))
+        );
+        assert_eq!(
+            dimension_type(OVERWORLD)
+                .unwrap()
+                .to_nbt()
+                .unwrap()
+                .root()
+                .get("fixed_time"),
+            None
+        );
+        assert!(dimension_type("minecraft:moon").is_err());
+    }
+
+    #[test]
+    fn codec_survives_the_wire() {
+        let codec = dimension_codec().unwrap();
+        let root = codec.root().clone();
+
+        let mut buf = BytesMut::new();
+        codec.write_to(&mut buf);
+
+        assert_eq!(Nbt::read_from(&mut buf).unwrap().root(), &root);
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use log::info;
+use rand::seq::IteratorRandom;
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::access::AccessLists;
+use crate::api::GameProfile;
+use crate::config::Config;
+use crate::protocol::data_types::Chat;
+use crate::protocol::packets::status;
+use crate::protocol::version::ProtocolVersion;
+use crate::registry;
+
+/// The most players listed in the server list sample, same as vanilla.
+const MAX_PLAYER_SAMPLE: usize = 12;
+
+/// The width and height the client requires the server icon to be.
+const FAVICON_SIZE: u32 = 64;
+
+struct Player {
+    name: String,
+    /// Identifies the connection the player is on, so a stale connection can't
+    /// remove a player that has since logged in again.
+    connection_id: u64,
+    /// Measured from keep alives, for the tab list.
+    latency: Duration,
+}
+
+/// State shared between every connection to the server.
+pub struct ServerState {
+    config: Arc<Config>,
+    /// The server icon as a `data:` URI, ready to be put in status responses.
+    favicon: Option<String>,
+    access_lists: AccessLists,
+    world: World,
+    players: Mutex<HashMap<Uuid, Player>>,
+    next_connection_id: AtomicU64,
+    next_entity_id: AtomicI32,
+    /// Tells every connection the server is stopping. Each connection holds a
+    /// receiver, so this also tracks how many are still open.
+    shutdown: broadcast::Sender<()>,
+}
+
+impl ServerState {
+    pub fn new(
+        config: Arc<Config>,
+        favicon: Option<String>,
+        access_lists: AccessLists,
+    ) -> ServerState {
+        let (shutdown, _) = broadcast::channel(1);
+
+        ServerState {
+            world: World::new(&config),
+            config,
+            favicon,
+            access_lists,
+            players: Mutex::new(HashMap::new()),
+            next_connection_id: AtomicU64::new(0),
+            next_entity_id: AtomicI32::new(0),
+            shutdown,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The server icon as a `data:` URI.
+    pub fn favicon(&self) -> Option<&str> {
+        self.favicon.as_deref()
+    }
+
+    pub fn access_lists(&self) -> &AccessLists {
+        &self.access_lists
+    }
+
+    /// Why a player can't join from `address`, or None if they can. Whether
+    /// the server has room for them is checked by `add_player`.
+    pub fn login_refusal(&self, profile: &GameProfile, address: Option<IpAddr>) -> Option<Chat> {
+        self.access_lists
+            .refusal(profile, address, self.config.white_list, Utc::now())
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn online_count(&self) -> usize {
+        self.players.lock().unwrap().len()
+    }
+
+    /// Marks a player as online. They stay online until the returned handle is
+    /// dropped. Returns None if the server is full.
+    pub fn add_player(self: &Arc<Self>, profile: &GameProfile) -> Option<OnlinePlayer> {
+        let mut players = self.players.lock().unwrap();
+
+        // A player logging in again takes over their old slot
+        if players.len() >= self.config.max_players && !players.contains_key(&profile.uuid) {
+            return None;
+        }
+
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let entity_id = self.next_entity_id.fetch_add(1, Ordering::Relaxed);
+
+        players.insert(
+            profile.uuid,
+            Player {
+                name: profile.name.clone(),
+                connection_id,
+                latency: Duration::from_millis(0),
+            },
+        );
+
+        Some(OnlinePlayer {
+            state: self.clone(),
+            uuid: profile.uuid,
+            connection_id,
+            entity_id,
+        })
+    }
+
+    /// The latency of an online player.
+    // Read by the tab list, once there is one
+    #[allow(dead_code)]
+    pub fn latency(&self, uuid: &Uuid) -> Option<Duration> {
+        let players = self.players.lock().unwrap();
+        players.get(uuid).map(|player| player.latency)
+    }
+
+    /// Subscribes a connection to be told when the server is stopping.
+    pub fn subscribe_shutdown(&self) -> broadcast::Receiver<()> {
+        self.shutdown.subscribe()
+    }
+
+    /// Tells every connection to disconnect its client.
+    pub fn shutdown(&self) {
+        // Fails only if there are no connections
+        let _ = self.shutdown.send(());
+    }
+
+    /// The number of connections still open, including ones that haven't
+    /// logged in.
+    pub fn connection_count(&self) -> usize {
+        self.shutdown.receiver_count()
+    }
+
+    /// Builds the response to a server list ping from the current state,
+    /// advertising `version` as the server's protocol version.
+    pub fn status(&self, version: ProtocolVersion) -> status::Response {
+        let players = self.players.lock().unwrap();
+
+        let sample = players
+            .iter()
+            .map(|(uuid, player)| (player.name.clone(), *uuid))
+            .choose_multiple(&mut rand::thread_rng(), MAX_PLAYER_SAMPLE);
+
+        status::Response::new(
+            version,
+            self.config.max_players,
+            players.len(),
+            sample,
+            motd_component(&self.config.motd),
+            self.favicon.clone(),
+        )
+    }
+}
+
+/// A handle to a player that is online. The player is removed from the server
+/// state when this is dropped.
+pub struct OnlinePlayer {
+    state: Arc<ServerState>,
+    uuid: Uuid,
+    connection_id: u64,
+    entity_id: i32,
+}
+
+impl OnlinePlayer {
+    /// The ID the player's entity is known by in the world.
+    pub fn entity_id(&self) -> i32 {
+        self.entity_id
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        let mut players = self.state.players.lock().unwrap();
+
+        if let Some(player) = players.get_mut(&self.uuid) {
+            if player.connection_id == self.connection_id {
+                player.latency = latency;
+            }
+        }
+    }
+}
+
+impl Drop for OnlinePlayer {
+    fn drop(&mut self) {
+        let mut players = self.state.players.lock().unwrap();
+
+        if let Some(player) = players.get(&self.uuid) {
+            if player.connection_id == self.connection_id {
+                players.remove(&self.uuid);
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GameMode {
+    Survival,
+    Creative,
+    Adventure,
+    Spectator,
+}
+
+impl GameMode {
+    pub fn id(self) -> u8 {
+        match self {
+            GameMode::Survival => 0,
+            GameMode::Creative => 1,
+            GameMode::Adventure => 2,
+            GameMode::Spectator => 3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Survival => "survival",
+            GameMode::Creative => "creative",
+            GameMode::Adventure => "adventure",
+            GameMode::Spectator => "spectator",
+        }
+    }
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for GameMode {
+    type Err = ();
+
+    /// Parses a game mode by name, or by ID like older versions of vanilla.
+    fn from_str(s: &str) -> Result<GameMode, ()> {
+        [
+            GameMode::Survival,
+            GameMode::Creative,
+            GameMode::Adventure,
+            GameMode::Spectator,
+        ]
+        .iter()
+        .copied()
+        .find(|mode| s.eq_ignore_ascii_case(mode.name()) || s == mode.id().to_string())
+        .ok_or(())
+    }
+}
+
+/// The world players join.
+pub struct World {
+    /// The world's name, which the client also uses as the dimension's name.
+    pub name: &'static str,
+    pub dimension_type: &'static str,
+    pub seed: i64,
+    pub is_flat: bool,
+    /// Where players appear when they join.
+    pub spawn: [f64; 3],
+}
+
+impl World {
+    fn new(config: &Config) -> World {
+        World {
+            name: registry::OVERWORLD,
+            dimension_type: registry::OVERWORLD,
+            seed: parse_seed(&config.level_seed),
+            is_flat: config.level_type.eq_ignore_ascii_case("flat"),
+            spawn: [0.5, 64.0, 0.5],
+        }
+    }
+
+    /// The seed as sent to the client, which only needs it for biome noise so
+    /// it gets a hash rather than the real thing.
+    pub fn hashed_seed(&self) -> i64 {
+        let hash = openssl::sha::sha256(&self.seed.to_le_bytes());
+        let mut first = [0; 8];
+        first.copy_from_slice(&hash[..8]);
+        i64::from_le_bytes(first)
+    }
+}
+
+/// Turns the `level-seed` property into a seed the same way vanilla does.
+/// Anything that isn't a number is hashed like a Java string. There's no level
+/// storage yet, so an empty seed gives a new world every start.
+fn parse_seed(seed: &str) -> i64 {
+    if seed.is_empty() {
+        return rand::random();
+    }
+
+    seed.parse().unwrap_or_else(|_| {
+        seed.encode_utf16().fold(0i32, |hash, unit| {
+            hash.wrapping_mul(31).wrapping_add(unit as i32)
+        }) as i64
+    })
+}
+
+/// Turns the configured MOTD into a chat component. A MOTD that is already a
+/// JSON chat component is used as is, anything else is treated as plain text
+/// (which can still contain legacy `§` formatting codes).
+fn motd_component(motd: &str) -> Value {
+    let trimmed = motd.trim_start();
+
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        if let Ok(component) = serde_json::from_str(trimmed) {
+            return component;
+        }
+    }
+
+    json!({ "text": motd })
+}
+
+/// Loads the server icon from a PNG file, returning it as a `data:` URI. A
+/// missing icon isn't an error, but an icon that isn't a 64x64 PNG is.
+pub fn load_favicon(path: &Path) -> Result<Option<String>> {
+    let png = match fs::read(path) {
+        Ok(png) => png,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    // The signature is followed by the IHDR chunk which holds the dimensions.
+    if png.len() < 24 || &png[..8] != b"\x89PNG\r\n\x1a\n" || &png[12..16] != b"IHDR" {
+        return Err(anyhow!("{} is not a PNG file", path.display()));
+    }
+
+    let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+    let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+
+    if width != FAVICON_SIZE || height != FAVICON_SIZE {
+        return Err(anyhow!(
+            "{} is {}x{} but must be {}x{}",
+            path.display(),
+            width,
+            height,
+            FAVICON_SIZE,
+            FAVICON_SIZE
+        ));
+    }
+
+    info!("Loaded server icon from {}", path.display());
+
+    Ok(Some(format!(
+        "data:image/png;base64,{}",
+        base64::encode(&png)
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str) -> GameProfile {
+        GameProfile::offline(name).unwrap()
+    }
+
+    #[test]
+    fn players_are_removed_when_dropped() {
+        let state = Arc::new(ServerState::new(
+            Arc::new(Config::default()),
+            None,
+            AccessLists::default(),
+        ));
+
+        let notch = state.add_player(&profile("Notch")).unwrap();
+        let jeb = state.add_player(&profile("jeb_")).unwrap();
+        assert_eq!(state.online_count(), 2);
+
+        drop(notch);
+        assert_eq!(state.online_count(), 1);
+
+        // Logging in again replaces the old connection, which then can't
+        // remove the new one.
+        let jeb_again = state.add_player(&profile("jeb_")).unwrap();
+        jeb_again.set_latency(Duration::from_millis(20));
+        jeb.set_latency(Duration::from_millis(500));
+        drop(jeb);
+        assert_eq!(state.online_count(), 1);
+        assert_eq!(
+            state.latency(&jeb_again.uuid),
+            Some(Duration::from_millis(20))
+        );
+
+        drop(jeb_again);
+        assert_eq!(state.online_count(), 0);
+    }
+
+    #[test]
+    fn full_server_turns_players_away() {
+        let config = Config {
+            max_players: 1,
+            ..Config::default()
+        };
+        let state = Arc::new(ServerState::new(
+            Arc::new(config),
+            None,
+            AccessLists::default(),
+        ));
+
+        let _notch = state.add_player(&profile("Notch")).unwrap();
+        assert!(state.add_player(&profile("jeb_")).is_none());
+        // Logging in again doesn't need another slot
+        assert!(state.add_player(&profile("Notch")).is_some());
+    }
+
+    #[test]
+    fn seeds_are_parsed_like_vanilla() {
+        assert_eq!(parse_seed("-12345"), -12345);
+        // "hello".hashCode() in Java
+        assert_eq!(parse_seed("hello"), 99162322);
+        assert_eq!("Creative".parse(), Ok(GameMode::Creative));
+        assert_eq!("3".parse(), Ok(GameMode::Spectator));
+        assert!("hardcore".parse::<GameMode>().is_err());
+    }
+
+    #[test]
+    fn motd_can_be_a_chat_component() {
+        assert_eq!(
+            motd_component(r#"{"text": "Hi", "color": "red"}"#),
+            json!({"text": "Hi", "color": "red"})
+        );
+        assert_eq!(
+            motd_component("\u{a7}aHello {world}"),
+            json!({"text": "\u{a7}aHello {world}"})
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::state::{self, ServerState};
+
+/// Servers sharing the same port, picked between by the address clients
+/// connected with. Each one has its own config, icon, world and players.
+///
+/// Every connection starts out on the default host and stays subscribed to its
+/// shutdown, so shutting the default host down stops them all.
+pub struct VirtualHosts {
+    default: Arc<ServerState>,
+    /// Keyed by hostname, as returned by `hostname`.
+    hosts: HashMap<String, Arc<ServerState>>,
+}
+
+impl VirtualHosts {
+    /// Loads the virtual hosts in `dir`. Each one is a directory named after
+    /// its hostname, with a `server.properties` for any options that differ
+    /// from the default host's and optionally its own `server-icon.png`, ban
+    /// lists and whitelist.
+    /// Options only the listener uses, like the port or forwarding mode, are
+    /// ignored.
+    pub fn load(dir: &Path, default: Arc<ServerState>) -> Result<VirtualHosts> {
+        let mut hosts = HashMap::new();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(VirtualHosts { default, hosts });
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+
+            let name = hostname(&path.file_name().unwrap().to_string_lossy());
+
+            let config = default
+                .config()
+                .load_overrides(&path.join("server.properties"))?;
+            let favicon = match state::load_favicon(&path.join("server-icon.png"))? {
+                Some(favicon) => Some(favicon),
+                None => default.favicon().map(str::to_string),
+            };
+            let access_lists = default.access_lists().load_overrides(&path)?;
+
+            info!("Loaded virtual host {}", name);
+            let host = ServerState::new(Arc::new(config), favicon, access_lists);
+            hosts.insert(name, Arc::new(host));
+        }
+
+        Ok(VirtualHosts { default, hosts })
+    }
+
+    /// The host for clients whose address doesn't match any other.
+    pub fn default_host(&self) -> &Arc<ServerState> {
+        &self.default
+    }
+
+    /// The host a client connected to, from the server address in its
+    /// handshake.
+    pub fn route(&self, server_address: &str) -> &Arc<ServerState> {
+        self.hosts
+            .get(&hostname(server_address))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// The hostname a client connected with, as used to pick a virtual host.
+///
+/// Forge clients add a `\0FML\0` marker to the address, and BungeeCord adds
+/// the player's details to it, both separated by null characters, so only the
+/// part before the first one is the hostname. Hostnames are case insensitive,
+/// and the trailing dot an SRV record can leave is dropped.
+pub fn hostname(server_address: &str) -> String {
+    let host = server_address.split('\0').next().unwrap_or_default();
+
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::access::AccessLists;
+    use crate::config::Config;
+    use crate::state::GameMode;
+
+    #[test]
+    fn hostnames_are_stripped_of_extra_data() {
+        assert_eq!(hostname("Play.Example.com"), "play.example.com");
+        assert_eq!(hostname("play.example.com."), "play.example.com");
+        assert_eq!(hostname("play.example.com\0FML\0"), "play.example.com");
+        assert_eq!(hostname("play.example.com\0FML2\0"), "play.example.com");
+        let forwarded = ["play.example.com", "203.0.113.7", "069a79f4", "[]"].join("\0");
+        assert_eq!(hostname(&forwarded), "play.example.com");
+    }
+
+    #[test]
+    fn hosts_override_the_default_config() {
+        let dir = std::env::temp_dir().join(format!("mcserver-hosts-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("Creative.Example.com")).unwrap();
+        fs::write(
+            dir.join("Creative.Example.com").join("server.properties"),
+            "motd=Creative\ngamemode=creative\n",
+        )
+        .unwrap();
+
+        let config = Config {
+            max_players: 5,
+            ..Config::default()
+        };
+        let default = Arc::new(ServerState::new(
+            Arc::new(config),
+            None,
+            AccessLists::default(),
+        ));
+        let hosts = VirtualHosts::load(&dir, default).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let creative = hosts.route("creative.example.com\0FML\0").config();
+        assert_eq!(creative.motd, "Creative");
+        assert_eq!(creative.gamemode, GameMode::Creative);
+        // Anything not overridden comes from the default host
+        assert_eq!(creative.max_players, 5);
+
+        let other = hosts.route("survival.example.com");
+        assert!(Arc::ptr_eq(other, hosts.default_host()));
+    }
+
+    #[test]
+    fn no_directory_means_no_hosts() {
+        let default = Arc::new(ServerState::new(
+            Arc::new(Config::default()),
+            None,
+            AccessLists::default(),
+        ));
+        let hosts = VirtualHosts::load(Path::new("does-not-exist"), default).unwrap();
+
+        assert!(hosts.hosts.is_empty());
+    }
+}